@@ -1,32 +1,576 @@
+use crate::config;
 use crate::config::*;
 use crate::dotfiles::*;
+use crate::integrity::Checksums;
+use crate::layers::*;
+use crate::paths;
+use crate::perm::{Owner, Perms};
+use crate::transfer;
 use crate::util::*;
 use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::File;
 use std::io;
 use std::io::Write;
-use std::path::{Component, PathBuf};
-
-pub use crate::config::init;
-
-pub fn list(config: &PathBuf) -> Result<()> {
-    let config = Config::load(config)?;
-    let dotfiles = Dotfiles::load(&config)?;
-    for file in dotfiles.get_files() {
-        println!(
-            "{}",
-            result_from_option(
-                file.to_str(),
-                format!("{:?} is not a valid UTF-8 path", file)
-            )?
-        )
+use std::os::unix::fs as unix;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const README: &str = "# Dotfiles
+
+Managed by dotfilesctl. The files in `contents` are symlinked into the home directory as listed in
+`dotfiles.toml`.
+";
+
+const GITIGNORE: &str = "# temporary files written by dotfilesctl
+*.toml.tmp
+";
+
+// where untrack keeps the deleted content until all files have been untracked, so that it can be
+// restored if one of them fails
+const UNTRACK_BACKUP: &str = "untrack.bak";
+
+// writes a scaffolding file unless it already exists
+fn scaffold(path: &Path, contents: &str) -> Result<()> {
+    if path.exists() {
+        warn!("{:?} already exists, not overwriting it", path);
+    } else {
+        info!("Creating {:?}", path);
+        File::create(path)?.write_all(contents.as_bytes())?;
+    }
+    Ok(())
+}
+
+pub fn init(
+    sources: &Sources,
+    target: &PathBuf,
+    readme: bool,
+    gitignore: bool,
+    force: bool,
+) -> Result<()> {
+    if !target.exists() {
+        info!("Creating {:?}", target);
+        fs::create_dir_all(target)?;
+    }
+    config::init(sources.user(), target, sources.home(), force)?;
+
+    let config = Config::new(target.canonicalize()?, sources.home());
+    let existing = Dotfiles::scaffold(&config)?;
+    if readme {
+        scaffold(&config.target().join("README.md"), README)?;
+    }
+    if gitignore {
+        scaffold(&config.target().join(".gitignore"), GITIGNORE)?;
+    }
+
+    if existing {
+        info!(
+            "{:?} already contains dotfiles, validating",
+            config.target()
+        );
+        let layers = Layers::load(&config)?;
+        if let Err(err) = layers.check() {
+            warn!("{}", err);
+            print!("Repair the home directory [y/N]? ");
+            io::stdout().flush()?;
+            let mut buffer = String::new();
+            io::stdin().read_line(&mut buffer)?;
+            match buffer.as_str().trim() {
+                // --force only replaces the configuration, every deletion is confirmed
+                "y" => check_layers(&layers, true, false)?,
+                _ => warn!("Not repairing, run check --repair later"),
+            }
+        } else {
+            info!("Checking successful!");
+        }
+    }
+    Ok(())
+}
+
+pub fn config_get(sources: &Sources, key: &str) -> Result<()> {
+    for value in sources.load()?.get(key)? {
+        println!("{}", value.display());
+    }
+    Ok(())
+}
+
+pub fn config_validate(sources: &Sources) -> Result<()> {
+    let mut problems = vec![];
+    match sources.get_unknown_keys() {
+        Ok(unknown) => {
+            for (path, key) in unknown {
+                problems.push(format!("Unknown key {:?} in {:?}", key, path));
+            }
+        }
+        Err(err) => problems.push(format!("{:#}", err)),
+    }
+    let config = match sources.load() {
+        Ok(config) => config,
+        Err(err) => {
+            // a file that cannot be parsed has been reported already
+            let problem = format!("{:#}", err);
+            if !problems.contains(&problem) {
+                problems.push(problem);
+            }
+            return report_problems(&problems);
+        }
+    };
+    for key in ["allow", "deny"] {
+        for rule in config.get(key)? {
+            if let Err(err) = glob::Pattern::new(&rule.to_string_lossy()) {
+                problems.push(format!(
+                    "Invalid {} rule {:?} from {}: {}",
+                    key,
+                    rule,
+                    config.origin(key).unwrap_or(Origin::Default),
+                    err
+                ));
+            }
+        }
+    }
+    match config.get_home() {
+        Ok(home) if home.is_dir() => {}
+        Ok(home) => problems.push(format!("Home directory {:?} does not exist", home)),
+        Err(err) => problems.push(err.to_string()),
+    }
+    for target in config.targets() {
+        if !target.is_dir() {
+            problems.push(format!("Target {:?} does not exist", target));
+            continue;
+        }
+        let layer = config.layer(target.clone());
+        if !layer.dotfiles().is_file() {
+            problems.push(format!("Target {:?} contains no manifest", target));
+        }
+        if !layer.contents().is_dir() {
+            problems.push(format!(
+                "Target {:?} contains no contents directory",
+                target
+            ));
+        }
+    }
+
+    // a target inside a tracked path would be moved into its own contents
+    if problems.is_empty() {
+        let home = config.get_home()?;
+        let layers = Layers::load(&config)?;
+        for target in config.targets() {
+            let resolved = target.canonicalize()?;
+            for layer in layers.iter() {
+                for entry in layer.dotfiles.get_entries() {
+                    let tracked = home.join(&entry);
+                    if target.starts_with(&tracked)
+                        || tracked
+                            .canonicalize()
+                            .is_ok_and(|tracked| resolved.starts_with(tracked))
+                    {
+                        problems.push(format!(
+                            "Target {:?} is inside {:?}, which is tracked in the layer {}",
+                            target, entry, layer.name
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    report_problems(&problems)
+}
+
+fn report_problems(problems: &[String]) -> Result<()> {
+    if problems.is_empty() {
+        info!("Configuration is valid.");
+        Ok(())
+    } else {
+        for problem in problems {
+            warn!("{}", problem);
+        }
+        Err(anyhow!(
+            "Found {} problem(s) in the configuration",
+            problems.len()
+        ))?
+    }
+}
+
+pub fn config_show(sources: &Sources, origin: bool) -> Result<()> {
+    let config = sources.load()?;
+    for (key, value) in config.values()? {
+        match config.origin(key) {
+            Some(from) if origin => println!("{} = {}\t# {}", key, value, from),
+            _ => println!("{} = {}", key, value),
+        }
+    }
+    Ok(())
+}
+
+pub fn list(sources: &Sources) -> Result<()> {
+    let config = sources.load()?;
+    let layers = Layers::load(&config)?;
+    for (layer, shadowed) in layers.iter().zip(layers.get_shadowed()) {
+        let dotfiles = layer.dotfiles.without(&shadowed);
+        for file in dotfiles
+            .get_files()
+            .into_iter()
+            .chain(dotfiles.get_links().into_keys())
+        {
+            let file = result_from_option(
+                file.to_str().map(String::from),
+                format!("{:?} is not a valid UTF-8 path", file),
+            )?;
+            if layers.len() > 1 {
+                println!("{}\t{}", file, layer.name)
+            } else {
+                println!("{}", file)
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn status(sources: &Sources) -> Result<()> {
+    let config = sources.load()?;
+    let layers = Layers::load(&config)?;
+    for (layer, shadowed) in layers.iter().zip(layers.get_shadowed()) {
+        let dotfiles = layer.dotfiles.without(&shadowed);
+        for (entry, state) in dotfiles.get_states(&layer.config)? {
+            println!("{}\t{}\t{}", state, entry.to_string_lossy(), layer.name)
+        }
+    }
+    Ok(())
+}
+
+// saves the manifest after a mutating command and records the checksums of the content
+fn save(config: &Config, dotfiles: &Dotfiles) -> Result<()> {
+    dotfiles.save(config)?;
+    Checksums::record(config, dotfiles)
+}
+
+pub fn bootstrap(sources: &Sources, target: &PathBuf, dry_run: bool, force: bool) -> Result<()> {
+    if !target.join("dotfiles.toml").is_file() || !target.join("contents").is_dir() {
+        Err(anyhow!(
+            "{:?} does not contain a manifest and a contents directory",
+            target
+        ))?
+    }
+    let home = sources
+        .home()
+        .as_deref()
+        .map(std::path::absolute)
+        .transpose()?;
+    let config = Config::new(target.canonicalize()?, home);
+    let layers = Layers::load(&config)?;
+    layers.check_contents()?;
+
+    let home = config.get_home()?;
+    let conflicts = layers.get_conflicts()?;
+    if conflicts.is_empty() {
+        info!("No conflicts with existing files in {:?}", home);
+    } else {
+        warn!("The following files in {:?} will be replaced:", home);
+        for conflict in &conflicts {
+            warn!("  {:?}", conflict);
+        }
     }
+    if dry_run {
+        return Ok(());
+    }
+
+    // the configuration is only written once the home directory is set up
+    if sources.user().is_file() && !force {
+        Err(anyhow!(
+            "{:?} exists but --force has not been specified",
+            sources.user()
+        ))?
+    }
+    if !conflicts.is_empty() {
+        let backup = home.join(format!(".{}-backup", APP_NAME)).join(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)?
+                .as_secs()
+                .to_string(),
+        );
+        if !force {
+            print!("Move {} file(s) to {:?} [y/N]? ", conflicts.len(), backup);
+            io::stdout().flush()?;
+            let mut buffer = String::new();
+            io::stdin().read_line(&mut buffer)?;
+            if buffer.trim() != "y" {
+                Err(anyhow!("Not bootstrapping"))?
+            }
+        }
+        for conflict in conflicts {
+            let destination = backup.join(paths::relative_to(&home, &conflict));
+            info!("Backing up {:?} to {:?}", conflict, destination);
+            if let Some(parent) = destination.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            transfer::move_item(&conflict, &destination)?;
+        }
+    }
+
+    if layers.repair(repair_behaviour(force))? == RepairResult::Skipped {
+        warn!("Skipped some files, problems remain")
+    }
+    layers.check()?;
+    for layer in layers.iter() {
+        Checksums::check(&layer.config, &layer.dotfiles)?;
+    }
+    config::init(sources.user(), target, sources.home(), force)
+}
+
+// the location of a target in the archive directory of eject
+fn archived(archive: &Path, target: &Path) -> Result<PathBuf> {
+    let name = result_from_option(target.file_name(), format!("{:?} has no file name", target))?;
+    Ok(archive.join(name))
+}
+
+pub fn eject(sources: &Sources, archive: Option<&Path>, force: bool) -> Result<()> {
+    let config = sources.load()?;
+    if let Some(archive) = archive {
+        let mut done = true;
+        for target in config.targets() {
+            done &= !target.exists() && archived(archive, &target)?.exists();
+        }
+        if done {
+            info!("All targets have already been ejected and archived");
+            return Ok(());
+        }
+    }
+    let layers = Layers::load(&config)?;
+    layers.check_contents()?;
+    if !force {
+        print!(
+            "Replace all symlinks in {:?} by copies of their content [y/N]? ",
+            config.get_home()?
+        );
+        io::stdout().flush()?;
+        let mut buffer = String::new();
+        io::stdin().read_line(&mut buffer)?;
+        if buffer.trim() != "y" {
+            Err(anyhow!("Not ejecting"))?
+        }
+    }
+    layers.eject()?;
+    info!("All dotfiles have been ejected");
+
+    if let Some(archive) = archive {
+        fs::create_dir_all(archive)?;
+        for target in config.targets() {
+            let destination = archived(archive, &target)?;
+            if destination.exists() && !target.exists() {
+                info!("{:?} has already been archived", target);
+                continue;
+            }
+            info!("Archiving {:?} to {:?}", target, destination);
+            transfer::move_item(&target, &destination)?;
+        }
+        info!(
+            "The configuration {:?} is no longer needed and can be removed",
+            sources.user()
+        );
+    }
+    Ok(())
+}
+
+// the manifests whose included manifests change when moving `old` to `new`, at their location
+// after the move, with their original and rewritten contents
+fn rebase_includes(layers: &Layers, old: &Path, new: &Path) -> Vec<(Config, Dotfiles, Dotfiles)> {
+    let rebase = |path: PathBuf| match path.strip_prefix(old) {
+        Ok(rest) => new.join(rest),
+        Err(_) => path,
+    };
+    let mut rebased = vec![];
+    for layer in layers.iter() {
+        let base = layer.config.target();
+        let new_base = rebase(base.clone());
+        let dotfiles = layer.dotfiles.rebase_include(&base, &new_base, old, new);
+        if dotfiles.get_include() != layer.dotfiles.get_include() {
+            let config = match layer.config.manifest() {
+                Some(manifest) => layer.config.include(rebase(manifest)),
+                None => layer.config.layer(new_base),
+            };
+            rebased.push((config, layer.dotfiles.clone(), dotfiles));
+        }
+    }
+    rebased
+}
+
+// points the configuration, included manifests and all symlinks to the moved target
+fn relocate_links(
+    sources: &Sources,
+    config: &Config,
+    old: &Path,
+    new: &Path,
+    rebased: &[(Config, Dotfiles, Dotfiles)],
+) -> Result<()> {
+    for (config, _, dotfiles) in rebased {
+        info!(
+            "Rewriting the included manifests of {:?}",
+            config.dotfiles()
+        );
+        dotfiles.save(config)?;
+    }
+    let targets = config
+        .targets()
+        .into_iter()
+        .map(|target| {
+            if target == old {
+                new.to_path_buf()
+            } else {
+                target
+            }
+        })
+        .collect::<Vec<_>>();
+    sources.set("target", &targets)?;
+    let layers = Layers::load(&sources.load()?)?;
+    layers.repoint(old, new)?;
+    layers.check()
+}
+
+pub fn relocate(sources: &Sources, new: &Path, layer: Option<&str>) -> Result<()> {
+    let config = sources.load()?;
+    let layers = Layers::load(&config)?;
+    layers.check()?;
+    let old = layers.get(layer)?.config.target();
+    if !config.targets().contains(&old) {
+        Err(anyhow!(
+            "{:?} is included by another manifest and cannot be relocated",
+            old
+        ))?
+    }
+    match config.origin("target") {
+        Some(Origin::File(ref path)) if path == sources.user() => {}
+        origin => Err(anyhow!(
+            "The target is configured by {}, but only {:?} can be rewritten",
+            origin.unwrap_or(Origin::Default),
+            sources.user()
+        ))?,
+    }
+    let new = std::path::absolute(new)?;
+    if new.symlink_metadata().is_ok() {
+        Err(anyhow!("{:?} already exists", new))?
+    }
+
+    let original = fs::read(sources.user())?;
+    let rebased = rebase_includes(&layers, &old, &new);
+    info!("Moving {:?} to {:?}", old, new);
+    transfer::move_item(&old, &new)?;
+    if let Err(err) = relocate_links(sources, &config, &old, &new, &rebased) {
+        warn!("Relocating failed, rolling back");
+        fs::write(sources.user(), original)?;
+        for (config, dotfiles, _) in &rebased {
+            dotfiles.save(config)?;
+        }
+        transfer::move_item(&new, &old)?;
+        Layers::load(&config)?.repoint(&new, &old)?;
+        Err(err)?
+    }
+    info!("Relocated {:?} to {:?}", old, new);
     Ok(())
 }
 
-pub fn check(config: &PathBuf, repair: bool, force: bool) -> Result<()> {
-    let config = Config::load(config)?;
-    let dotfiles = Dotfiles::load(&config)?;
+pub fn migrate_home(sources: &Sources, new: &Path, remove_old: bool, force: bool) -> Result<()> {
+    let config = sources.load()?;
+    let rewritable = |key: &str| match config.origin(key) {
+        Some(Origin::File(ref path)) if path == sources.user() => Ok(()),
+        None | Some(Origin::Default) if key == "home" => Ok(()),
+        origin => Err(anyhow!(
+            "The {} is configured by {}, but only {:?} can be rewritten",
+            key,
+            origin.unwrap_or(Origin::Default),
+            sources.user()
+        )),
+    };
+    rewritable("home")?;
+    let old = config.get_home()?;
+    let new = std::path::absolute(new)?;
+    if !new.is_dir() {
+        Err(anyhow!("{:?} is not a directory", new))?
+    }
+    // the old home directory may be gone already
+    if paths::canonicalize_existing(&new) == paths::canonicalize_existing(&old) {
+        Err(anyhow!("{:?} already is the home directory", new))?
+    }
 
+    // targets inside the old home directory move along if they are found in the new one
+    let targets = config
+        .targets()
+        .into_iter()
+        .map(|target| match target.strip_prefix(&old) {
+            Ok(rest) if new.join(rest).is_dir() => new.join(rest),
+            _ => target,
+        })
+        .collect::<Vec<_>>();
+    if targets != config.targets() {
+        rewritable("target")?;
+    }
+    // symlinks in the old home directory can only be removed while it still has its targets
+    let layers = if remove_old {
+        Some(Layers::load(&config)?)
+    } else {
+        None
+    };
+
+    let original = fs::read(sources.user()).ok();
+    let mut rebased = vec![];
+    if let Err(err) = migrate_config(sources, &targets, &old, &new, &mut rebased) {
+        warn!("Migrating failed, rolling back");
+        match original {
+            Some(original) => fs::write(sources.user(), original)?,
+            None => fs::remove_file(sources.user())?,
+        }
+        for (config, dotfiles) in &rebased {
+            save(config, dotfiles)?;
+        }
+        Err(err)?
+    }
+
+    let migrated = Layers::load(&sources.load()?)?;
+    let conflicts = migrated.get_conflicts()?;
+    if !conflicts.is_empty() {
+        warn!("The following files in {:?} conflict with dotfiles:", new);
+        for conflict in &conflicts {
+            warn!("  {:?}", conflict);
+        }
+    }
+    if migrated.repair(repair_behaviour(force))? == RepairResult::Skipped {
+        warn!("Skipped some files, problems remain")
+    }
+    migrated.check()?;
+
+    if let Some(layers) = layers {
+        info!("Removing symlinks from {:?}", old);
+        layers.unlink()?;
+    }
+    Ok(())
+}
+
+// points the configuration to the new home directory and moves links into the old one along,
+// remembering the original manifests in `rebased` for rolling back
+fn migrate_config(
+    sources: &Sources,
+    targets: &[PathBuf],
+    old: &Path,
+    new: &Path,
+    rebased: &mut Vec<(Config, Dotfiles)>,
+) -> Result<()> {
+    if sources.load()?.targets() != targets {
+        sources.set("target", targets)?;
+    }
+    sources.set("home", &[new.to_path_buf()])?;
+    let migrated = Layers::load(&sources.load()?)?;
+    migrated.check_contents()?;
+    for layer in migrated.iter() {
+        let dotfiles = layer.dotfiles.rebase_links(old, new);
+        if dotfiles.get_links() != layer.dotfiles.get_links() {
+            save(&layer.config, &dotfiles)?;
+            rebased.push((layer.config.clone(), layer.dotfiles.clone()));
+        }
+    }
+    Ok(())
+}
+
+fn repair_behaviour(force: bool) -> fn(&PathBuf) -> Result<RepairAction> {
     fn force_behaviour(_: &PathBuf) -> Result<RepairAction> {
         Ok(RepairAction::Delete)
     }
@@ -43,25 +587,26 @@ pub fn check(config: &PathBuf, repair: bool, force: bool) -> Result<()> {
         }
     }
 
-    match dotfiles.check(&config) {
+    if force {
+        force_behaviour
+    } else {
+        ask_behaviour
+    }
+}
+
+// checks all layers and optionally attempts to repair problems
+fn check_layers(layers: &Layers, repair: bool, force: bool) -> Result<()> {
+    match layers.check() {
         Ok(()) => info!("Checking successful!"),
         Err(err) => {
             if repair {
                 warn!("Found problems during checking:");
                 warn!("{}", err);
                 info!("Attempting to repair problems");
-                let result = dotfiles.repair(
-                    &config,
-                    if force {
-                        force_behaviour
-                    } else {
-                        ask_behaviour
-                    },
-                )?;
-                match result {
+                match layers.repair(repair_behaviour(force))? {
                     RepairResult::Successful => {
                         info!("Rechecking");
-                        dotfiles.check(&config)?
+                        layers.check()?
                     }
                     RepairResult::Skipped => {
                         warn!("Skipped some files, problems remain")
@@ -72,64 +617,248 @@ pub fn check(config: &PathBuf, repair: bool, force: bool) -> Result<()> {
             }
         }
     }
+    Ok(())
+}
 
-    dotfiles.save(&config)?;
+pub fn check(
+    sources: &Sources,
+    repair: bool,
+    integrity: bool,
+    move_ignored: bool,
+    force: bool,
+) -> Result<()> {
+    let config = sources.load()?;
+    let layers = Layers::load(&config)?;
+    check_layers(&layers, repair, force)?;
+
+    for layer in layers.iter() {
+        if integrity {
+            Checksums::check(&layer.config, &layer.dotfiles)?;
+        }
+        let ignored = layer.dotfiles.get_ignored_files(&layer.config.contents())?;
+        if ignored.is_empty() {
+            layer.dotfiles.save(&layer.config)?;
+        } else if move_ignored {
+            let dotfiles = layer.dotfiles.move_ignored(&layer.config, save)?;
+            save(&layer.config, &dotfiles)?;
+        } else {
+            warn!(
+                "Ignored content in tracked directories (see check --move-ignored): {:?}",
+                ignored
+            );
+            layer.dotfiles.save(&layer.config)?;
+        }
+    }
     Ok(())
 }
 
-pub fn track(config: &PathBuf, file: &PathBuf, skip_check: bool, force: bool) -> Result<()> {
-    let config = Config::load(config)?;
-    let dotfiles = Dotfiles::load(&config)?;
+fn relative_behaviour(force: bool) -> fn(&Config, &Path) -> Result<()> {
+    // deny rules hold even when forced
+    fn force_behaviour(config: &Config, path: &Path) -> Result<()> {
+        config.check_denied(path)
+    }
+
+    fn check_behaviour(config: &Config, path: &Path) -> Result<()> {
+        config.check_trackable(path)
+    }
+
+    if force {
+        force_behaviour
+    } else {
+        check_behaviour
+    }
+}
+
+// reverts the changes an operation made to the file system, before its manifest is saved
+type Undo = Box<dyn FnOnce() -> Result<()>>;
+
+fn nothing_to_undo() -> Undo {
+    Box::new(|| Ok(()))
+}
+
+// applies `apply` to the files of all batches one after another and saves the manifests only
+// once all of them succeeded. Otherwise, the completed operations are undone in reverse order and
+// no manifest is modified
+fn apply_all<F>(batches: &[(&Layer, Vec<PathBuf>)], apply: F) -> Result<()>
+where
+    F: Fn(&Layer, &Dotfiles, &PathBuf) -> Result<(Dotfiles, Undo)>,
+{
+    let mut updated = vec![];
+    let mut undos = vec![];
+    let mut result = Ok(());
+    'batches: for (layer, files) in batches {
+        let mut dotfiles = layer.dotfiles.clone();
+        for file in files {
+            match apply(layer, &dotfiles, file) {
+                Ok((next, undo)) => {
+                    dotfiles = next;
+                    undos.push((file, undo));
+                }
+                Err(err) => {
+                    result = Err(err);
+                    break 'batches;
+                }
+            }
+        }
+        updated.push((*layer, dotfiles));
+    }
+    if result.is_err() {
+        for (file, undo) in undos.into_iter().rev() {
+            info!("Reverting {:?}", file);
+            if let Err(err) = undo() {
+                warn!("Cannot revert {:?}: {}", file, err);
+            }
+        }
+        return result;
+    }
+    for (layer, dotfiles) in updated {
+        save(&layer.config, &dotfiles)?;
+    }
+    Ok(())
+}
+
+// groups the files by their supplying layer, after checking each of them with `check`
+fn by_layer<F>(
+    layers: &Layers,
+    files: Vec<PathBuf>,
+    check: F,
+) -> Result<Vec<(&Layer, Vec<PathBuf>)>>
+where
+    F: Fn(&Layer, &PathBuf) -> Result<PathBuf>,
+{
+    let mut batches: Vec<(&Layer, Vec<PathBuf>)> = vec![];
+    for file in files {
+        let layer = layers.supplying(&file)?;
+        check(layer, &file)?;
+        match batches
+            .iter_mut()
+            .find(|(other, _)| std::ptr::eq(*other, layer))
+        {
+            Some((_, batch)) => batch.push(file),
+            None => batches.push((layer, vec![file])),
+        }
+    }
+    Ok(batches)
+}
+
+pub fn track(
+    sources: &Sources,
+    files: &[PathBuf],
+    layer: Option<&str>,
+    skip_check: bool,
+    force: bool,
+) -> Result<()> {
+    let config = sources.load()?;
+    let layers = Layers::load(&config)?;
     if skip_check {
         warn!("Skipping check, this is potentially dangerous")
     } else {
-        dotfiles.check(&config)?;
+        layers.check()?;
     }
 
-    fn force_behaviour(_: &PathBuf) -> Result<()> {
-        Ok(())
+    let files = layers.expand(files)?;
+    let layer = layers.get(layer)?;
+    let mut tracked: BTreeMap<PathBuf, PathBuf> = BTreeMap::new();
+    for file in &files {
+        layers.check_untracked(layer, file)?;
+        let relative =
+            layer
+                .dotfiles
+                .check_track(&layer.config, file, relative_behaviour(force))?;
+        if let Some(other) = tracked
+            .values()
+            .find(|other| paths::overlaps(other, &relative))
+        {
+            Err(anyhow!(
+                "Cannot track {:?} because it overlaps with {:?}",
+                relative,
+                other
+            ))?
+        }
+        tracked.insert(file.clone(), relative);
     }
-
-    fn check_behaviour(path: &PathBuf) -> Result<()> {
-        match path.components().next().unwrap() {
-            Component::Normal(str) => {
-                let str = result_from_option(
-                    str.to_str(),
-                    format!("{:?} is not a valid UTF-8 path", path),
-                )?;
-                if !str.starts_with('.') {
-                    Err(anyhow!(
-                        "Only dotfiles can be tracked, {:?} does not start with a dot",
-                        path
-                    ))?
+    apply_all(&[(layer, files)], |layer, dotfiles, file| {
+        let is_link = file.symlink_metadata()?.file_type().is_symlink();
+        let relative = tracked[file].clone();
+        let dotfiles = dotfiles.track(&layer.config, file, relative_behaviour(force))?;
+        if is_link {
+            return Ok((dotfiles, nothing_to_undo()));
+        }
+        let contents = layer.config.contents();
+        let file = file.clone();
+        let undo = move || {
+            let content = contents.join(&relative);
+            if file.is_symlink() {
+                fs::remove_file(&file)?;
+                transfer::move_item(&content, &file)?;
+            } else {
+                // a directory with ignored content, which has been linked leaf by leaf
+                for entry in walk(&content)? {
+                    let leaf = content.join(&entry);
+                    if !leaf.symlink_metadata()?.is_dir() {
+                        fs::remove_file(file.join(&entry))?;
+                        transfer::move_item(&leaf, &file.join(&entry))?;
+                    }
                 }
+                transfer::delete_item(&content)?;
             }
-            _ => Err(anyhow!(
-                "Only dotfiles can be tracked, {:?} does not start with a normal path component",
-                path
-            ))?,
+            // remove the parents that have been created for the content
+            for parent in content.ancestors().skip(1) {
+                if parent == contents || fs::remove_dir(parent).is_err() {
+                    break;
+                }
+            }
+            Ok(())
         };
-        Ok(())
+        Ok((dotfiles, Box::new(undo) as Undo))
+    })
+}
+
+pub fn adopt_orphans(sources: &Sources, force: bool) -> Result<()> {
+    let config = sources.load()?;
+    let layers = Layers::load(&config)?;
+
+    fn force_behaviour(_: &PathBuf) -> Result<bool> {
+        Ok(true)
     }
 
-    dotfiles
-        .track(
-            &config,
-            file,
+    fn ask_behaviour(path: &PathBuf) -> Result<bool> {
+        print!("Adopt {:?} [y/N]? ", path);
+        io::stdout().flush()?;
+        let mut buffer = String::new();
+        io::stdin().read_line(&mut buffer)?;
+        match buffer.as_str().trim() {
+            "" | "N" => Ok(false),
+            "y" => Ok(true),
+            _ => Err(anyhow!("Invalid answer: {}", buffer))?,
+        }
+    }
+
+    for layer in layers.iter() {
+        let dotfiles = layer.dotfiles.adopt_orphans(
+            &layer.config,
             if force {
                 force_behaviour
             } else {
-                check_behaviour
+                ask_behaviour
             },
-        )?
-        .save(&config)?;
+        )?;
+        save(&layer.config, &dotfiles)?;
+    }
     Ok(())
 }
 
-pub fn untrack(config: &PathBuf, file: &PathBuf, force: bool) -> Result<()> {
-    let config = Config::load(config)?;
-    let dotfiles = Dotfiles::load(&config)?;
-    dotfiles.check(&config)?;
+pub fn untrack(sources: &Sources, files: &[PathBuf], force: bool) -> Result<()> {
+    let config = sources.load()?;
+    let layers = Layers::load(&config)?;
+    let files = layers.expand(files)?;
+    let home = config.get_home()?;
+    // the symlinks of the untracked files may be broken or missing
+    let relatives = files
+        .iter()
+        .filter_map(|file| paths::home_relative(&home, file))
+        .collect::<Vec<_>>();
+    layers.check_except(&relatives)?;
 
     fn force_behaviour(_: &PathBuf) -> Result<()> {
         Ok(())
@@ -146,26 +875,436 @@ pub fn untrack(config: &PathBuf, file: &PathBuf, force: bool) -> Result<()> {
         }
     }
 
-    dotfiles
-        .untrack(
-            &config,
-            file,
-            if force {
-                force_behaviour
-            } else {
-                ask_behaviour
-            },
-        )?
-        .save(&config)?;
+    let confirm_delete = if force {
+        force_behaviour
+    } else {
+        ask_behaviour
+    };
+    let batches = by_layer(&layers, files, |layer, file| {
+        layer.dotfiles.check_untrack(&layer.config, file)
+    })?;
+    let backups = batches
+        .iter()
+        .map(|(layer, _)| layer.config.target().join(UNTRACK_BACKUP))
+        .collect::<Vec<_>>();
+    if let Some(backup) = backups.iter().find(|backup| backup.exists()) {
+        Err(anyhow!(
+            "{:?} is left over from an interrupted untrack, restore or remove it first",
+            backup
+        ))?
+    }
+    let result = apply_all(&batches, |layer, dotfiles, file| {
+        let relative = dotfiles.check_untrack(&layer.config, file)?;
+        let content = layer.config.contents().join(&relative);
+        let backup = layer.config.target().join(UNTRACK_BACKUP).join(&relative);
+        let is_link = dotfiles.get_links().contains_key(&relative);
+        if !is_link && content.symlink_metadata().is_ok() {
+            fs::create_dir_all(result_from_option(
+                backup.parent(),
+                format!("Invalid backup path {:?}", backup),
+            )?)?;
+            transfer::copy_item(&content, &backup)?;
+        }
+        let link = file.read_link().ok();
+        let unfolded = dotfiles.get_unfolded().contains(&relative);
+        let dotfiles = dotfiles.untrack(&layer.config, file, confirm_delete)?;
+        let file = file.clone();
+        let undo = move || {
+            if backup.symlink_metadata().is_ok() && content.symlink_metadata().is_err() {
+                if let Some(parent) = content.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                transfer::move_item(&backup, &content)?;
+            }
+            if let Some(link) = link {
+                if file.symlink_metadata().is_err() {
+                    unix::symlink(link, &file)?;
+                }
+            }
+            // an unfolded directory is linked leaf by leaf
+            if unfolded {
+                fs::create_dir_all(&file)?;
+                for entry in walk(&content)? {
+                    let (leaf, path) = (content.join(&entry), file.join(&entry));
+                    if leaf.symlink_metadata()?.is_dir() {
+                        fs::create_dir_all(path)?;
+                    } else if path.symlink_metadata().is_err() {
+                        unix::symlink(leaf, path)?;
+                    }
+                }
+            }
+            Ok(())
+        };
+        Ok((dotfiles, Box::new(undo) as Undo))
+    });
+    for backup in backups {
+        if backup.exists() {
+            transfer::delete_item(&backup)?;
+        }
+    }
+    result
+}
+
+pub fn mv(sources: &Sources, from: &Path, to: &Path, force: bool) -> Result<()> {
+    let config = sources.load()?;
+    let layers = Layers::load(&config)?;
+    let from = layers.resolve(from)?;
+    let to = layers.resolve(to)?;
+    // the symlink of the moved file may be missing
+    let relative = paths::home_relative(&config.get_home()?, &from);
+    layers.check_except(relative.as_slice())?;
+    let layer = layers.supplying(&from)?;
+    layers.check_untracked(layer, &to)?;
+    let dotfiles = layer
+        .dotfiles
+        .mv(&layer.config, &from, &to, relative_behaviour(force))?;
+    save(&layer.config, &dotfiles)?;
+    Ok(())
+}
+
+pub fn merge(sources: &Sources, file: &Path) -> Result<()> {
+    let config = sources.load()?;
+    let layers = Layers::load(&config)?;
+    let file = layers.resolve(file)?;
+    let layer = layers.supplying(&file)?;
+    save(&layer.config, &layer.dotfiles.merge(&layer.config, &file)?)?;
     Ok(())
 }
 
-pub fn set_executable(config: &PathBuf, file: &PathBuf, mode: Executable) -> Result<()> {
-    let config = Config::load(config)?;
-    let dotfiles = Dotfiles::load(&config)?;
-    dotfiles.check(&config)?;
-    dotfiles
-        .set_executable(&config, file, mode)?
-        .save(&config)?;
+pub fn fold(sources: &Sources, dir: &Path) -> Result<()> {
+    let config = sources.load()?;
+    let layers = Layers::load(&config)?;
+    layers.check()?;
+    let dir = layers.resolve(dir)?;
+    let layer = layers.supplying(&dir)?;
+    save(&layer.config, &layer.dotfiles.fold(&layer.config, &dir)?)?;
     Ok(())
 }
+
+pub fn unfold(sources: &Sources, dir: &Path) -> Result<()> {
+    let config = sources.load()?;
+    let layers = Layers::load(&config)?;
+    layers.check()?;
+    let dir = layers.resolve(dir)?;
+    let layer = layers.supplying(&dir)?;
+    save(&layer.config, &layer.dotfiles.unfold(&layer.config, &dir)?)?;
+    Ok(())
+}
+
+pub fn dir(sources: &Sources, dir: &Path, mode: Option<Perms>, remove: bool) -> Result<()> {
+    let config = sources.load()?;
+    let layers = Layers::load(&config)?;
+    layers.check()?;
+    let dir = layers.resolve(dir)?;
+    let layer = layers.supplying(&dir)?;
+    let dotfiles = if remove {
+        layer.dotfiles.undeclare_dir(&layer.config, &dir)?
+    } else {
+        layer.dotfiles.declare_dir(&layer.config, &dir, mode)?
+    };
+    save(&layer.config, &dotfiles)?;
+    Ok(())
+}
+
+pub fn set_executable(sources: &Sources, files: &[PathBuf], mode: Executable) -> Result<()> {
+    let config = sources.load()?;
+    let layers = Layers::load(&config)?;
+    layers.check()?;
+    let files = layers.expand(files)?;
+    let batches = by_layer(&layers, files, |layer, file| {
+        layer
+            .dotfiles
+            .check_set_executable(&layer.config, file, mode)
+    })?;
+    apply_all(&batches, |layer, dotfiles, file| {
+        let relative = dotfiles.check_set_executable(&layer.config, file, mode)?;
+        let content = layer.config.contents().join(relative);
+        let permissions = fs::symlink_metadata(&content)?.permissions();
+        let dotfiles = dotfiles.set_executable(&layer.config, file, mode)?;
+        let undo = move || Ok(fs::set_permissions(&content, permissions)?);
+        Ok((dotfiles, Box::new(undo) as Undo))
+    })
+}
+
+pub fn set_owner(sources: &Sources, files: &[PathBuf], owner: Option<Owner>) -> Result<()> {
+    let config = sources.load()?;
+    let layers = Layers::load(&config)?;
+    layers.check()?;
+    let files = layers.expand(files)?;
+    let batches = by_layer(&layers, files, |layer, file| {
+        layer.dotfiles.check_set_owner(&layer.config, file, owner)
+    })?;
+    apply_all(&batches, |layer, dotfiles, file| {
+        let relative = dotfiles.check_set_owner(&layer.config, file, owner)?;
+        let previous = dotfiles
+            .get_owned_paths(
+                &layer.config.get_home()?,
+                &layer.config.contents(),
+                &relative,
+            )?
+            .into_iter()
+            .map(|path| Ok((Owner::get(&path)?, path)))
+            .collect::<Result<Vec<_>>>()?;
+        let dotfiles = dotfiles.set_owner(&layer.config, file, owner)?;
+        let undo = move || {
+            for (owner, path) in previous {
+                owner.set(&path)?;
+            }
+            Ok(())
+        };
+        Ok((dotfiles, Box::new(undo) as Undo))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::*;
+    use crate::config::test_util::*;
+    use crate::perm::is_root;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_init() {
+        let dir = TempDir::new().unwrap();
+        let home = dir.path().join("home");
+        fs::create_dir(&home).unwrap();
+        let sources = setup_sources(dir.path().join("config.toml"), Some(home.clone()));
+        let target = dir.path().join("target");
+        init(&sources, &target, true, true, false).unwrap();
+        let config = sources.load().unwrap();
+        assert_eq!(config.target(), target.canonicalize().unwrap());
+        assert!(config.contents().is_dir());
+        assert!(target.join(".gitignore").is_file());
+        assert!(Dotfiles::load(&config).unwrap().get_files().is_empty());
+
+        // an existing target is validated and kept as it is
+        fs::write(config.contents().join(".profile"), "").unwrap();
+        let dotfiles = Dotfiles::new(Some(vec![PathBuf::from(".profile")]), None, Some(vec![]));
+        save(&config, &dotfiles).unwrap();
+        unix::symlink(config.contents().join(".profile"), home.join(".profile")).unwrap();
+        fs::write(target.join("README.md"), "custom").unwrap();
+        assert!(init(&sources, &target, true, false, false).is_err());
+        sources.set("deny", &[PathBuf::from(".ssh")]).unwrap();
+        init(&sources, &target, true, false, true).unwrap();
+        // the other keys of the config survive a forced init
+        assert_eq!(
+            sources.load().unwrap().get("deny").unwrap(),
+            vec![PathBuf::from(".ssh")]
+        );
+        assert_eq!(
+            Dotfiles::load(&config).unwrap().get_files(),
+            dotfiles.get_files()
+        );
+        assert_eq!(
+            fs::read_to_string(target.join("README.md")).unwrap(),
+            "custom"
+        );
+    }
+
+    #[test]
+    fn test_config_validate() {
+        let dir = TempDir::new().unwrap();
+        let home = dir.path().join("home");
+        fs::create_dir(&home).unwrap();
+        let sources = setup_sources(dir.path().join("config.toml"), Some(home));
+        init(&sources, &dir.path().join("target"), false, false, false).unwrap();
+        config_validate(&sources).unwrap();
+
+        // written by hand, since setting an invalid pattern is refused
+        let user = fs::read_to_string(sources.user()).unwrap();
+        fs::write(sources.user(), format!("{}deny = [\"[\"]\n", user)).unwrap();
+        assert!(config_validate(&sources).is_err());
+    }
+
+    #[test]
+    fn test_bootstrap() {
+        let dir = TempDir::new().unwrap();
+        let home = dir.path().join("home");
+        fs::create_dir(&home).unwrap();
+        let target = dir.path().join("target");
+        fs::create_dir(&target).unwrap();
+        let config = Config::new(target.clone(), Some(home.clone()));
+        Dotfiles::scaffold(&config).unwrap();
+        fs::write(config.contents().join(".profile"), "tracked").unwrap();
+        let dotfiles = Dotfiles::new(Some(vec![PathBuf::from(".profile")]), None, Some(vec![]));
+        save(&config, &dotfiles).unwrap();
+        fs::write(home.join(".profile"), "existing").unwrap();
+        let sources = setup_sources(dir.path().join("config.toml"), Some(home.clone()));
+
+        bootstrap(&sources, &target, true, false).unwrap();
+        assert!(!home.join(".profile").is_symlink());
+        assert!(!sources.user().exists());
+
+        bootstrap(&sources, &target, false, true).unwrap();
+        assert_eq!(
+            home.join(".profile").read_link().unwrap(),
+            config.contents().join(".profile")
+        );
+        let backups = home.join(format!(".{}-backup", APP_NAME));
+        let backup = fs::read_dir(&backups).unwrap().next().unwrap().unwrap();
+        assert_eq!(
+            fs::read_to_string(backup.path().join(".profile")).unwrap(),
+            "existing"
+        );
+        assert_eq!(sources.load().unwrap().target(), target);
+    }
+
+    #[test]
+    fn test_system_ownership() {
+        let dir = TempDir::new().unwrap();
+        let sources = setup_sources(dir.path().join("config.toml"), None);
+        init(&sources, &dir.path().join("target"), false, false, false).unwrap();
+        let sources = sources.with_system_scope(true);
+        let config = sources.load().unwrap();
+        Dotfiles::scaffold(&config).unwrap();
+        let etc = dir.path().join("etc");
+        fs::create_dir(&etc).unwrap();
+        let relative = paths::relative_to(Path::new("/"), &etc.join("hosts"));
+        let content = config.contents().join(&relative);
+        fs::create_dir_all(content.parent().unwrap()).unwrap();
+        fs::write(&content, "").unwrap();
+        let dotfiles = Dotfiles::new(Some(vec![relative]), None, Some(vec![]));
+        save(&config, &dotfiles).unwrap();
+        // anyone could replace the target through its parent, and nothing is owned by root
+        // unless the tests run as root
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o777)).unwrap();
+
+        let err = check(&sources, true, false, false, true).unwrap_err();
+        if is_root() {
+            let writable = format!("{:?} is writable", dir.path());
+            assert!(err.to_string().contains(&writable));
+        } else {
+            assert!(err.to_string().contains("owned by"));
+        }
+        assert!(etc.join("hosts").symlink_metadata().is_err());
+        fs::write(etc.join("passwd"), "").unwrap();
+        assert!(track(&sources, &[etc.join("passwd")], None, true, true).is_err());
+        assert!(!etc.join("passwd").is_symlink());
+    }
+
+    #[test]
+    fn test_track_rollback() {
+        let dir = TempDir::new().unwrap();
+        let home = dir.path().join("home");
+        fs::create_dir_all(home.join(".config")).unwrap();
+        let sources = setup_sources(dir.path().join("config.toml"), Some(home.clone()));
+        init(&sources, &dir.path().join("target"), false, false, false).unwrap();
+        let config = sources.load().unwrap();
+        fs::write(home.join(".profile"), "profile").unwrap();
+        fs::write(home.join(".config/app"), "app").unwrap();
+        // the content of .config/app cannot be created below this file
+        fs::write(config.contents().join(".config"), "").unwrap();
+
+        let files = [home.join(".profile"), home.join(".config/app")];
+        assert!(track(&sources, &files, None, true, false).is_err());
+        assert!(!home.join(".profile").is_symlink());
+        assert_eq!(
+            fs::read_to_string(home.join(".profile")).unwrap(),
+            "profile"
+        );
+        assert!(!config.contents().join(".profile").exists());
+        assert!(Dotfiles::load(&config).unwrap().get_files().is_empty());
+
+        fs::remove_file(config.contents().join(".config")).unwrap();
+        track(&sources, &files, None, false, false).unwrap();
+        assert_eq!(Dotfiles::load(&config).unwrap().get_files().len(), 2);
+    }
+
+    #[test]
+    fn test_relocate() {
+        let dir = TempDir::new().unwrap();
+        let home = dir.path().join("home");
+        fs::create_dir(&home).unwrap();
+        let sources = setup_sources(dir.path().join("config.toml"), Some(home.clone()));
+        let target = dir.path().join("target");
+        init(&sources, &dir.path().join("shared"), false, false, false).unwrap();
+        init(&sources, &target, false, false, true).unwrap();
+        let config = sources.load().unwrap();
+        fs::write(
+            config.dotfiles(),
+            "version = 1\nfiles = [\".profile\"]\ninclude = [\"../shared/dotfiles.toml\"]\n",
+        )
+        .unwrap();
+        fs::write(config.contents().join(".profile"), "").unwrap();
+        unix::symlink(config.contents().join(".profile"), home.join(".profile")).unwrap();
+        Layers::load(&config).unwrap().check().unwrap();
+
+        let moved = dir.path().join("moved/target");
+        fs::create_dir(dir.path().join("moved")).unwrap();
+        relocate(&sources, &moved, None).unwrap();
+        let config = sources.load().unwrap();
+        assert_eq!(config.target(), moved);
+        assert_eq!(
+            Dotfiles::load(&config).unwrap().get_include(),
+            vec![PathBuf::from("../../shared/dotfiles.toml")]
+        );
+        assert_eq!(Layers::load(&config).unwrap().len(), 2);
+        assert_eq!(
+            home.join(".profile").read_link().unwrap(),
+            moved.join("contents/.profile")
+        );
+
+        // the configuration cannot be replaced, so everything is moved back
+        fs::create_dir(dir.path().join("config.toml.tmp")).unwrap();
+        assert!(relocate(&sources, &target, None).is_err());
+        let config = sources.load().unwrap();
+        assert_eq!(config.target(), moved);
+        assert!(target.symlink_metadata().is_err());
+        let layers = Layers::load(&config).unwrap();
+        assert_eq!(layers.len(), 2);
+        layers.check().unwrap();
+    }
+
+    #[test]
+    fn test_migrate_home() {
+        let dir = TempDir::new().unwrap();
+        let old = dir.path().join("old");
+        let target = old.join("dotfiles");
+        fs::create_dir(&old).unwrap();
+        let user = dir.path().join("config.toml");
+        init(
+            &setup_sources(user.clone(), Some(old.clone())),
+            &target,
+            false,
+            false,
+            false,
+        )
+        .unwrap();
+        let sources = setup_sources(user, None);
+        let config = sources.load().unwrap();
+        fs::write(config.contents().join(".profile"), "").unwrap();
+        fs::write(
+            config.dotfiles(),
+            format!(
+                "version = 1\nfiles = [\".profile\"]\n[links]\n\".data\" = {:?}\n",
+                old.join("data")
+            ),
+        )
+        .unwrap();
+        unix::symlink(config.contents().join(".profile"), old.join(".profile")).unwrap();
+        unix::symlink(old.join("data"), old.join(".data")).unwrap();
+        Layers::load(&config).unwrap().check().unwrap();
+
+        // the whole home directory including the target has moved
+        let new = dir.path().join("new");
+        fs::rename(&old, &new).unwrap();
+
+        // orphaned content fails the validation, so the configuration is restored
+        let orphan = new.join("dotfiles/contents/.orphan");
+        fs::write(&orphan, "").unwrap();
+        assert!(migrate_home(&sources, &new, false, true).is_err());
+        assert_eq!(sources.load().unwrap().get_home().unwrap(), old);
+        fs::remove_file(&orphan).unwrap();
+
+        migrate_home(&sources, &new, false, true).unwrap();
+        let config = sources.load().unwrap();
+        assert_eq!(config.get_home().unwrap(), new);
+        assert_eq!(config.target(), new.join("dotfiles"));
+        assert_eq!(
+            new.join(".profile").read_link().unwrap(),
+            new.join("dotfiles/contents/.profile")
+        );
+        assert_eq!(new.join(".data").read_link().unwrap(), new.join("data"));
+    }
+}
@@ -1,29 +1,519 @@
 use crate::util::*;
-use anyhow::Result;
+use anyhow::{Context, Result};
+use glob::{MatchOptions, Pattern};
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
 use std::fs::File;
-use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
 use xdg::BaseDirectories;
 
-#[derive(Debug, Deserialize, Serialize)]
+pub const SYSTEM_PATH: &str = "/etc/xdg/dotfilesctl/config.toml";
+pub const TARGET_VARIABLE: &str = "DOTFILESCTL_TARGET";
+pub const HOME_VARIABLE: &str = "DOTFILESCTL_HOME";
+pub const KEYS: [&str; 4] = ["target", "home", "allow", "deny"];
+
+fn check_key(key: &str) -> Result<()> {
+    if !KEYS.contains(&key) {
+        Err(anyhow!(
+            "Unknown configuration key {:?}, expected one of {}",
+            key,
+            KEYS.join(", ")
+        ))?
+    }
+    Ok(())
+}
+
+// either a single target or a list of targets, ordered from highest to lowest priority
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+enum Targets {
+    One(PathBuf),
+    Many(Vec<PathBuf>),
+}
+
+impl Targets {
+    fn from(mut targets: Vec<PathBuf>) -> Targets {
+        if targets.len() == 1 {
+            Targets::One(targets.remove(0))
+        } else {
+            Targets::Many(targets)
+        }
+    }
+
+    fn to_vec(&self) -> Vec<PathBuf> {
+        match self {
+            Targets::One(target) => vec![target.clone()],
+            Targets::Many(targets) => targets.clone(),
+        }
+    }
+}
+
+// the closest ancestor of `dir` with a manifest and a contents directory
+pub fn discover(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .find(|dir| dir.join("dotfiles.toml").is_file() && dir.join("contents").is_dir())
+        .map(Path::to_path_buf)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Origin {
+    Default,
+    File(PathBuf),
+    Environment(&'static str),
+    Flag(&'static str),
+    Discovered,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::File(path) => write!(f, "{:?}", path),
+            Origin::Environment(variable) => write!(f, "environment variable {}", variable),
+            Origin::Flag(flag) => write!(f, "flag {}", flag),
+            Origin::Discovered => write!(f, "working directory"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Origins {
+    target: Option<Origin>,
+    home: Option<Origin>,
+    allow: Option<Origin>,
+    deny: Option<Origin>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Config {
-    pub target: PathBuf,
+    target: Targets,
+    home: Option<PathBuf>,
+    // globs relative to the home directory that may be tracked even though they are no dotfiles
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    allow: Vec<String>,
+    // globs relative to the home directory that must not be tracked, taking precedence over allow
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    deny: Vec<String>,
+    // manifest of an included dotfile set, which is not necessarily called dotfiles.toml
+    #[serde(skip)]
+    manifest: Option<PathBuf>,
+    #[serde(skip)]
+    system_scope: bool,
+    #[serde(skip)]
+    origins: Origins,
+}
+
+// a configuration file in which all keys are optional
+#[derive(Default, Deserialize)]
+struct Partial {
+    target: Option<Targets>,
     home: Option<PathBuf>,
+    allow: Option<Vec<String>>,
+    deny: Option<Vec<String>>,
+}
+
+impl Partial {
+    fn load(path: &Path) -> Result<Partial> {
+        let mut contents = String::new();
+        File::open(path)?.read_to_string(&mut contents)?;
+        toml::from_str::<Partial>(contents.as_ref())
+            .with_context(|| format!("Invalid configuration {:?}", path))
+    }
+}
+
+fn environment(variable: &str) -> Option<OsString> {
+    env::var_os(variable)
+}
+
+// from lowest to highest precedence: system file, user file, environment and flags
+pub struct Sources {
+    system: PathBuf,
+    user: PathBuf,
+    // whether the user file has been specified explicitly and hence has to exist
+    explicit: bool,
+    environment: fn(&str) -> Option<OsString>,
+    // where to look for a target if none has been specified explicitly
+    working_dir: Option<PathBuf>,
+    target: Option<PathBuf>,
+    home: Option<PathBuf>,
+    system_scope: bool,
+}
+
+impl Sources {
+    pub fn new(
+        user: PathBuf,
+        explicit: bool,
+        target: Option<PathBuf>,
+        home: Option<PathBuf>,
+    ) -> Sources {
+        Sources {
+            system: PathBuf::from(SYSTEM_PATH),
+            user,
+            explicit,
+            environment,
+            working_dir: env::current_dir().ok(),
+            target,
+            home,
+            system_scope: false,
+        }
+    }
+
+    pub fn with_system_scope(self, system_scope: bool) -> Sources {
+        Sources {
+            system_scope,
+            ..self
+        }
+    }
+
+    pub fn user(&self) -> &PathBuf {
+        &self.user
+    }
+
+    // the user file as a table, which is empty if the file does not exist yet
+    fn user_table(&self) -> Result<toml::Table> {
+        read_table(&self.user)
+    }
+
+    fn save_user_table(&self, table: &toml::Table) -> Result<()> {
+        write_atomically(&self.user, &toml::to_string(table)?)
+    }
+
+    // warns if a value in the user file has no effect
+    fn check_overridden(&self, key: &str) {
+        if let Ok(config) = self.load() {
+            match config.origin(key) {
+                Some(Origin::File(ref path)) if *path == self.user => {}
+                Some(origin) => warn!("{} is overridden by {}", key, origin),
+                None => {}
+            }
+        }
+    }
+
+    // `home` accepts only a single value
+    pub fn set(&self, key: &str, values: &[PathBuf]) -> Result<()> {
+        check_key(key)?;
+        let value = if key == "allow" || key == "deny" {
+            let patterns = values
+                .iter()
+                .map(|value| value.to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+            for pattern in &patterns {
+                Pattern::new(pattern)?;
+            }
+            toml::Value::try_from(patterns)?
+        } else {
+            let values = values
+                .iter()
+                .map(std::path::absolute)
+                .collect::<std::io::Result<Vec<_>>>()?;
+            match key {
+                "home" if values.len() != 1 => Err(anyhow!("Expected a single home directory"))?,
+                "home" => toml::Value::try_from(&values[0])?,
+                _ => toml::Value::try_from(Targets::from(values))?,
+            }
+        };
+        info!("Setting {} to {} in {:?}", key, value, self.user);
+        let mut table = self.user_table()?;
+        table.insert(key.to_string(), value);
+        self.save_user_table(&table)?;
+        self.check_overridden(key);
+        Ok(())
+    }
+
+    pub fn unset(&self, key: &str) -> Result<()> {
+        check_key(key)?;
+        let mut table = self.user_table()?;
+        if table.remove(key).is_none() {
+            warn!("{} is not set in {:?}", key, self.user);
+            return Ok(());
+        }
+        info!("Removing {} from {:?}", key, self.user);
+        self.save_user_table(&table)
+    }
+
+    pub fn get_unknown_keys(&self) -> Result<Vec<(PathBuf, String)>> {
+        let mut unknown = vec![];
+        for path in [&self.system, &self.user] {
+            if !path.is_file() {
+                continue;
+            }
+            let mut contents = String::new();
+            File::open(path)?.read_to_string(&mut contents)?;
+            let table = toml::from_str::<toml::Table>(contents.as_ref())
+                .with_context(|| format!("Invalid configuration {:?}", path))?;
+            for key in table.keys() {
+                if !KEYS.contains(&key.as_str()) {
+                    unknown.push((path.clone(), key.clone()));
+                }
+            }
+        }
+        Ok(unknown)
+    }
+
+    pub fn home(&self) -> Option<PathBuf> {
+        self.home.clone()
+    }
+
+    pub fn load(&self) -> Result<Config> {
+        let mut target = None;
+        let mut home = None;
+        let mut allow = None;
+        let mut deny = None;
+
+        for (path, required) in [(&self.system, false), (&self.user, self.explicit)] {
+            if !required && !path.is_file() {
+                continue;
+            }
+            let partial = Partial::load(path)?;
+            if let Some(value) = partial.target {
+                target = Some((value, Origin::File(path.clone())));
+            }
+            if let Some(value) = partial.home {
+                home = Some((value, Origin::File(path.clone())));
+            }
+            if let Some(value) = partial.allow {
+                allow = Some((value, Origin::File(path.clone())));
+            }
+            if let Some(value) = partial.deny {
+                deny = Some((value, Origin::File(path.clone())));
+            }
+        }
+
+        let variable = |name| (self.environment)(name).filter(|value| !value.is_empty());
+        let explicit =
+            self.explicit || self.target.is_some() || variable(TARGET_VARIABLE).is_some();
+        if let Some(discovered) = self.working_dir.as_deref().and_then(discover) {
+            let configured = target
+                .as_ref()
+                .map_or(vec![], |(targets, _)| targets.to_vec());
+            let known = configured.iter().any(|target| {
+                target
+                    .canonicalize()
+                    .is_ok_and(|target| target == discovered)
+            });
+            // a configured target is never replaced silently by whatever the working directory
+            // happens to be in
+            match target {
+                Some((_, ref origin)) if !explicit && !known => warn!(
+                    "Ignoring {:?} found in the working directory, using the target configured in {}",
+                    discovered, origin
+                ),
+                None if !explicit => {
+                    target = Some((Targets::One(discovered), Origin::Discovered));
+                }
+                _ => {}
+            }
+        }
+        if let Some(value) = variable(TARGET_VARIABLE) {
+            let targets = env::split_paths(&value).collect();
+            target = Some((Targets::from(targets), Origin::Environment(TARGET_VARIABLE)));
+        }
+        if let Some(value) = variable(HOME_VARIABLE) {
+            home = Some((PathBuf::from(value), Origin::Environment(HOME_VARIABLE)));
+        }
+
+        if let Some(ref value) = self.target {
+            target = Some((Targets::One(value.clone()), Origin::Flag("--target")));
+        }
+        if let Some(ref value) = self.home {
+            home = Some((value.clone(), Origin::Flag("--home")));
+        }
+
+        let (target, target_origin) = result_from_option(
+            target,
+            format!("No target configured, run init or create {:?}", self.user),
+        )?;
+        if let Targets::Many(ref targets) = target {
+            if targets.is_empty() {
+                Err(anyhow!("Empty list of targets in {}", target_origin))?
+            }
+        }
+        let (allow, allow_origin) = allow.unwrap_or((vec![], Origin::Default));
+        let (deny, deny_origin) = deny.unwrap_or((vec![], Origin::Default));
+        Ok(Config {
+            target,
+            home: home.as_ref().map(|(home, _)| home.clone()),
+            allow,
+            deny,
+            manifest: None,
+            system_scope: self.system_scope,
+            origins: Origins {
+                target: Some(target_origin),
+                home: Some(home.map_or(Origin::Default, |(_, origin)| origin)),
+                allow: Some(allow_origin),
+                deny: Some(deny_origin),
+            },
+        })
+    }
 }
 
 impl Config {
     pub fn new(target: PathBuf, home: Option<PathBuf>) -> Config {
-        Config { target, home }
+        Config {
+            target: Targets::One(target),
+            home,
+            allow: vec![],
+            deny: vec![],
+            manifest: None,
+            system_scope: false,
+            origins: Origins::default(),
+        }
     }
 
-    pub fn load(config: &PathBuf) -> Result<Config> {
-        let mut contents = String::new();
-        File::open(config)?.read_to_string(&mut contents)?;
-        let config = toml::from_str::<Config>(contents.as_ref())?;
-        Ok(config)
+    // loads a single configuration file, ignoring the environment
+    #[cfg(test)]
+    pub fn load(config: &Path) -> Result<Config> {
+        fn no_environment(_: &str) -> Option<OsString> {
+            None
+        }
+
+        Sources {
+            system: PathBuf::new(),
+            user: config.to_path_buf(),
+            explicit: true,
+            environment: no_environment,
+            working_dir: None,
+            target: None,
+            home: None,
+            system_scope: false,
+        }
+        .load()
+    }
+
+    pub fn origin(&self, key: &str) -> Option<Origin> {
+        match key {
+            "target" => self.origins.target.clone(),
+            "home" => self.origins.home.clone(),
+            "allow" => self.origins.allow.clone(),
+            "deny" => self.origins.deny.clone(),
+            _ => None,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Result<Vec<PathBuf>> {
+        check_key(key)?;
+        match key {
+            "target" => Ok(self.targets()),
+            "allow" => Ok(self.allow.iter().map(PathBuf::from).collect()),
+            "deny" => Ok(self.deny.iter().map(PathBuf::from).collect()),
+            _ => Ok(vec![self.get_home()?]),
+        }
+    }
+
+    pub fn values(&self) -> Result<Vec<(&'static str, String)>> {
+        let target = toml::Value::try_from(&self.target)?.to_string();
+        let home = toml::Value::try_from(self.get_home()?)?.to_string();
+        let allow = toml::Value::try_from(&self.allow)?.to_string();
+        let deny = toml::Value::try_from(&self.deny)?.to_string();
+        Ok(vec![
+            ("target", target),
+            ("home", home),
+            ("allow", allow),
+            ("deny", deny),
+        ])
+    }
+
+    // the first of the given rules that matches the path or one of its parents
+    fn matching_rule<'a>(
+        &self,
+        rules: &'a [String],
+        relative: &Path,
+    ) -> Result<Option<&'a String>> {
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        for rule in rules {
+            let pattern = Pattern::new(rule)?;
+            if relative
+                .ancestors()
+                .any(|path| pattern.matches_path_with(path, options))
+            {
+                return Ok(Some(rule));
+            }
+        }
+        Ok(None)
+    }
+
+    // unlike the other rules of check_trackable, deny rules hold even with --force
+    pub fn check_denied(&self, relative: &Path) -> Result<()> {
+        // the rules are about the home directory
+        if self.system_scope {
+            return Ok(());
+        }
+        if let Some(rule) = self.matching_rule(&self.deny, relative)? {
+            Err(anyhow!(
+                "Cannot track {:?} because it matches the deny rule {:?} from {}",
+                relative,
+                rule,
+                self.origins.deny.as_ref().unwrap_or(&Origin::Default)
+            ))?
+        }
+        Ok(())
+    }
+
+    // deny takes precedence over allow, and paths that match neither must be dotfiles
+    pub fn check_trackable(&self, relative: &Path) -> Result<()> {
+        if self.system_scope {
+            return Ok(());
+        }
+        self.check_denied(relative)?;
+        if self.matching_rule(&self.allow, relative)?.is_some() {
+            return Ok(());
+        }
+        match relative.components().next() {
+            Some(Component::Normal(name)) if name.to_string_lossy().starts_with('.') => Ok(()),
+            _ => Err(anyhow!(
+                "Only dotfiles can be tracked, {:?} does not start with a dot and matches no allow rule",
+                relative
+            ))?,
+        }
+    }
+
+    pub fn targets(&self) -> Vec<PathBuf> {
+        match self.target {
+            Targets::One(ref target) => vec![target.clone()],
+            Targets::Many(ref targets) => targets.clone(),
+        }
+    }
+
+    pub fn target(&self) -> PathBuf {
+        self.targets()[0].clone()
+    }
+
+    pub fn layer(&self, target: PathBuf) -> Config {
+        Config {
+            target: Targets::One(target),
+            manifest: None,
+            ..self.clone()
+        }
+    }
+
+    // the contents of an included manifest are stored next to it
+    pub fn include(&self, manifest: PathBuf) -> Config {
+        let target = manifest.parent().unwrap_or(Path::new("/")).to_path_buf();
+        Config {
+            target: Targets::One(target),
+            manifest: Some(manifest),
+            ..self.clone()
+        }
+    }
+
+    pub fn manifest(&self) -> Option<PathBuf> {
+        self.manifest.clone()
+    }
+
+    // entries relative to `/`, listed in system.toml with their content in system
+    pub fn is_system_scope(&self) -> bool {
+        self.system_scope
     }
 
     pub fn get_home(&self) -> Result<PathBuf> {
+        if self.system_scope {
+            return Ok(PathBuf::from("/"));
+        }
         let path = match self.home.clone().or_else(dirs::home_dir) {
             Some(home) => Ok(home),
             None => Err(anyhow!(
@@ -34,11 +524,27 @@ impl Config {
     }
 
     pub fn dotfiles(&self) -> PathBuf {
-        self.target.join("dotfiles.toml")
+        match self.manifest {
+            Some(ref manifest) => manifest.clone(),
+            None if self.system_scope => self.target().join("system.toml"),
+            None => self.target().join("dotfiles.toml"),
+        }
     }
 
     pub fn contents(&self) -> PathBuf {
-        self.target.join("contents")
+        if self.system_scope {
+            self.target().join("system")
+        } else {
+            self.target().join("contents")
+        }
+    }
+
+    pub fn checksums(&self) -> PathBuf {
+        if self.system_scope {
+            self.target().join("system-checksums.toml")
+        } else {
+            self.target().join("checksums.toml")
+        }
     }
 }
 
@@ -48,16 +554,28 @@ pub fn get_path() -> Result<PathBuf> {
     Ok(path)
 }
 
+fn read_table(path: &Path) -> Result<toml::Table> {
+    if !path.is_file() {
+        return Ok(toml::Table::new());
+    }
+    let mut contents = String::new();
+    File::open(path)?.read_to_string(&mut contents)?;
+    toml::from_str::<toml::Table>(contents.as_ref())
+        .with_context(|| format!("Invalid configuration {:?}", path))
+}
+
 pub fn init(config: &PathBuf, target: &PathBuf, home: Option<PathBuf>, force: bool) -> Result<()> {
     if !target.is_dir() {
         Err(anyhow!("{:?} is not a directory", target))?
     } else {
         let target = target.canonicalize()?;
+        let home = home.as_deref().map(std::path::absolute).transpose()?;
         info!("Installing a fresh config in {:?}", config);
         if !config.is_file() || force {
-            let contents = toml::to_string(&Config::new(target, home))?;
-            File::create(config)?.write_all(contents.as_bytes())?;
-            Ok(())
+            // keeps the other keys of an existing config, e.g. allow and deny
+            let mut table = read_table(config)?;
+            table.extend(toml::Table::try_from(Config::new(target, home))?);
+            write_atomically(config, &toml::to_string(&table)?)
         } else {
             Err(anyhow!(
                 "{:?} exists but --force has not been specified",
@@ -70,6 +588,7 @@ pub fn init(config: &PathBuf, target: &PathBuf, home: Option<PathBuf>, force: bo
 #[cfg(test)]
 pub mod test_util {
     use crate::config::*;
+    use crate::dotfiles::Dotfiles;
     use std::fs;
     use tempfile::{Builder, TempDir};
 
@@ -86,18 +605,208 @@ pub mod test_util {
         let config = dir.path().join("config.toml");
         init(&config, &target, Some(home), false).unwrap();
         let config = Config::load(&config).unwrap();
-        assert_eq!(target, config.target);
-        fs::create_dir(config.contents()).unwrap();
+        assert_eq!(target, config.target());
+        Dotfiles::scaffold(&config).unwrap();
         (dir, config)
     }
+
+    // sources consisting of the given user file only, ignoring the system file and environment
+    pub fn setup_sources(user: PathBuf, home: Option<PathBuf>) -> Sources {
+        fn no_environment(_: &str) -> Option<OsString> {
+            None
+        }
+
+        Sources {
+            system: PathBuf::new(),
+            user,
+            explicit: true,
+            environment: no_environment,
+            working_dir: None,
+            target: None,
+            home,
+            system_scope: false,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::config::test_util::*;
+    use crate::config::*;
+    use std::fs;
 
     #[test]
     fn test_setup() {
         setup_config();
     }
+
+    #[test]
+    fn test_targets() {
+        let config = toml::from_str::<Config>(r#"target = ["/high", "/low"]"#).unwrap();
+        assert_eq!(
+            config.targets(),
+            vec![PathBuf::from("/high"), PathBuf::from("/low")]
+        );
+        assert_eq!(config.target(), PathBuf::from("/high"));
+        assert_eq!(config.layer(PathBuf::from("/low")).targets().len(), 1);
+
+        let config = toml::from_str::<Config>(r#"target = "/single""#).unwrap();
+        assert_eq!(config.targets(), vec![PathBuf::from("/single")]);
+    }
+
+    #[test]
+    fn test_layered() {
+        fn environment(variable: &str) -> Option<OsString> {
+            match variable {
+                HOME_VARIABLE => Some(OsString::from("/env/home")),
+                _ => None,
+            }
+        }
+
+        let (dir, _) = setup_config();
+        let system = dir.path().join("system.toml");
+        fs::write(&system, "target = \"/system\"\nhome = \"/system/home\"\n").unwrap();
+        let mut sources = Sources {
+            system,
+            user: dir.path().join("missing.toml"),
+            explicit: false,
+            environment,
+            working_dir: None,
+            target: None,
+            home: None,
+            system_scope: false,
+        };
+        let config = sources.load().unwrap();
+        assert_eq!(config.target(), PathBuf::from("/system"));
+        assert_eq!(config.get_home().unwrap(), PathBuf::from("/env/home"));
+        assert_eq!(
+            config.origin("target"),
+            Some(Origin::File(dir.path().join("system.toml")))
+        );
+        assert_eq!(
+            config.origin("home"),
+            Some(Origin::Environment(HOME_VARIABLE))
+        );
+
+        sources.user = dir.path().join("config.toml");
+        sources.home = Some(PathBuf::from("/flag/home"));
+        let config = sources.load().unwrap();
+        assert_eq!(config.target(), dir.path().join("target"));
+        assert_eq!(config.get_home().unwrap(), PathBuf::from("/flag/home"));
+        assert_eq!(config.origin("home"), Some(Origin::Flag("--home")));
+
+        sources.explicit = true;
+        sources.user = dir.path().join("missing.toml");
+        assert!(sources.load().is_err());
+    }
+
+    #[test]
+    fn test_system_scope() {
+        let (dir, _) = setup_config();
+        let sources =
+            Sources::new(dir.path().join("config.toml"), true, None, None).with_system_scope(true);
+        let config = sources.load().unwrap();
+        let target = dir.path().join("target");
+        assert!(config.is_system_scope());
+        assert_eq!(config.get_home().unwrap(), PathBuf::from("/"));
+        assert_eq!(config.dotfiles(), target.join("system.toml"));
+        assert_eq!(config.contents(), target.join("system"));
+        assert!(config.layer(target.clone()).is_system_scope());
+        // the rules only apply to the home directory
+        assert!(config.check_trackable(Path::new("etc/hosts")).is_ok());
+    }
+
+    #[test]
+    fn test_rules() {
+        let (dir, _) = setup_config();
+        let sources = Sources::new(dir.path().join("config.toml"), true, None, None);
+        sources
+            .set(
+                "allow",
+                &[PathBuf::from("bin"), PathBuf::from("Templates/*")],
+            )
+            .unwrap();
+        sources.set("deny", &[PathBuf::from(".ssh")]).unwrap();
+        assert!(sources.set("deny", &[PathBuf::from("[")]).is_err());
+        let config = Config::load(&sources.user).unwrap();
+        assert_eq!(
+            config.get("allow").unwrap(),
+            vec![PathBuf::from("bin"), PathBuf::from("Templates/*")]
+        );
+
+        assert!(config.check_trackable(Path::new(".vimrc")).is_ok());
+        assert!(config.check_trackable(Path::new("bin/foo")).is_ok());
+        assert!(config.check_trackable(Path::new("Templates/a")).is_ok());
+        assert!(config.check_trackable(Path::new("Templates")).is_err());
+        assert!(config.check_trackable(Path::new("Documents")).is_err());
+        let err = config
+            .check_trackable(Path::new(".ssh/config"))
+            .unwrap_err();
+        assert!(err.to_string().contains("deny rule \".ssh\""));
+        // deny rules also hold for tracking that is forced
+        assert!(config.check_denied(Path::new(".ssh/config")).is_err());
+        assert!(config.check_denied(Path::new("Documents")).is_ok());
+    }
+
+    #[test]
+    fn test_set_unset() {
+        let (dir, _) = setup_config();
+        let mut sources = Sources::new(dir.path().join("config.toml"), true, None, None);
+        sources.system = PathBuf::new();
+        sources.working_dir = None;
+        fs::write(
+            &sources.user,
+            format!(
+                "{}unknown = 1\n",
+                fs::read_to_string(&sources.user).unwrap()
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            sources.get_unknown_keys().unwrap(),
+            vec![(sources.user.clone(), "unknown".to_string())]
+        );
+
+        sources
+            .set("target", &[PathBuf::from("/high"), PathBuf::from("/low")])
+            .unwrap();
+        sources.unset("home").unwrap();
+        let config = Config::load(&sources.user).unwrap();
+        assert_eq!(
+            config.get("target").unwrap(),
+            vec![PathBuf::from("/high"), PathBuf::from("/low")]
+        );
+        assert_eq!(config.origin("home"), Some(Origin::Default));
+        assert!(sources.set("home", &[]).is_err());
+        assert!(sources.set("unknown", &[PathBuf::from("/")]).is_err());
+    }
+
+    #[test]
+    fn test_discover() {
+        let (dir, config) = setup_config();
+        let other = dir.path().join("other");
+        fs::create_dir_all(other.join("contents/.vim")).unwrap();
+        fs::write(other.join("dotfiles.toml"), "").unwrap();
+        let mut sources = Sources::new(dir.path().join("config.toml"), false, None, None);
+        sources.system = PathBuf::new();
+
+        // the configured target takes precedence
+        sources.working_dir = Some(other.join("contents/.vim"));
+        assert_eq!(sources.load().unwrap().target(), config.target());
+
+        sources.user = dir.path().join("missing.toml");
+        let discovered = sources.load().unwrap();
+        assert_eq!(discovered.target(), other);
+        assert_eq!(discovered.origin("target"), Some(Origin::Discovered));
+
+        sources.explicit = true;
+        assert!(sources.load().is_err());
+
+        sources.user = dir.path().join("config.toml");
+        assert_eq!(sources.load().unwrap().target(), config.target());
+
+        sources.explicit = false;
+        sources.working_dir = Some(dir.path().to_path_buf());
+        assert_eq!(sources.load().unwrap().target(), config.target());
+    }
 }
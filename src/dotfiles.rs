@@ -1,13 +1,17 @@
 use crate::config::*;
 use crate::paths;
+use crate::perm;
+use crate::perm::{Owner, Perms, SPECIAL_MASK};
+use crate::transfer;
 use crate::util::*;
 use anyhow::{Error, Result};
-use fs_extra::dir::CopyOptions;
-use std::collections::HashMap;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::fs::OpenOptions;
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read};
 use std::os::unix::fs as unix;
+use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 use std::vec::Vec;
 use toml::Value;
@@ -60,8 +64,10 @@ impl Symlink {
     }
 
     pub fn get(contents: &Path, home: &Path, dotfile: &PathBuf) -> Symlink {
-        let expected = contents.join(dotfile);
-        let symlink = home.join(dotfile);
+        Symlink::at(contents.join(dotfile), home.join(dotfile))
+    }
+
+    pub fn at(expected: PathBuf, symlink: PathBuf) -> Symlink {
         match symlink.symlink_metadata() {
             Ok(_) => match symlink.read_link() {
                 Ok(actual) => Symlink::new(
@@ -88,10 +94,23 @@ impl Symlink {
     pub fn repair(
         &self,
         wrong_behaviour: fn(&PathBuf) -> Result<RepairAction>,
-        mode: Executable,
+        mode: Option<Executable>,
     ) -> Result<RepairResult> {
         let result = match self.status {
             SymlinkStatus::Wrong => {
+                // the path may lead to the content itself, e.g. through a symlinked parent directory
+                let expected = match self.path.parent() {
+                    Some(parent) => parent.join(&self.expected),
+                    None => self.expected.clone(),
+                };
+                let resolved = self.path.canonicalize().ok();
+                if resolved.is_some() && resolved == expected.canonicalize().ok() {
+                    Err(anyhow!(
+                        "Refusing to delete {:?} because it resolves to the content {:?}",
+                        self.path,
+                        self.expected
+                    ))?
+                }
                 let action = wrong_behaviour(&self.path)?;
                 match action {
                     RepairAction::Skip => {
@@ -111,7 +130,9 @@ impl Symlink {
                 RepairResult::Successful
             }
             SymlinkStatus::Ok => {
-                self.set_executable(mode)?;
+                if let Some(mode) = mode {
+                    self.set_executable(mode)?;
+                }
                 RepairResult::Successful
             }
         };
@@ -119,6 +140,40 @@ impl Symlink {
         Ok(result)
     }
 
+    // the copy is put next to the symlink first, so that an interrupted eject can be resumed
+    pub fn eject(&self) -> Result<()> {
+        let name = result_from_option(
+            self.path.file_name(),
+            format!("{:?} has no file name", self.path),
+        )?;
+        let mut copy_name = name.to_os_string();
+        copy_name.push(format!(".{}-eject", APP_NAME));
+        let copy = self.path.with_file_name(copy_name);
+
+        if let SymlinkStatus::Wrong = self.status {
+            warn!("{:?} is not a managed symlink, skipping", self.path);
+            return Ok(());
+        }
+        if copy.symlink_metadata().is_ok() {
+            if transfer::verify_item(&self.expected, &copy).is_ok() {
+                info!("Resuming eject of {:?}", self.path);
+            } else {
+                warn!("Discarding incomplete copy {:?}", copy);
+                transfer::delete_item(&copy)?;
+            }
+        }
+        if copy.symlink_metadata().is_err() {
+            info!("Copying {:?} to {:?}", self.expected, self.path);
+            transfer::copy_item(&self.expected, &copy)?;
+            transfer::verify_item(&self.expected, &copy)?;
+        }
+        if let SymlinkStatus::Ok = self.status {
+            fs::remove_file(&self.path)?;
+        }
+        fs::rename(&copy, &self.path)?;
+        Ok(())
+    }
+
     pub fn set_executable(&self, mode: Executable) -> Result<()> {
         if self.expected.is_file() {
             mode.set(&self.expected)?
@@ -127,12 +182,126 @@ impl Symlink {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum DirectoryStatus {
+    Ok,
+    Absent,
+    Wrong,
+    Mode(Perms),
+    // a symlink to a directory, possibly holding data that lives elsewhere
+    Symlink(PathBuf),
+}
+
+pub struct Directory {
+    pub path: PathBuf,
+    pub mode: Perms,
+    pub status: DirectoryStatus,
+}
+
+impl Directory {
+    pub fn get(home: &Path, dir: &PathBuf, mode: Perms) -> Result<Directory> {
+        let path = home.join(dir);
+        let status = match path.symlink_metadata() {
+            Ok(meta) if meta.is_dir() => {
+                let actual = Perms::from_mode(meta.permissions().mode());
+                if actual == mode {
+                    DirectoryStatus::Ok
+                } else {
+                    DirectoryStatus::Mode(actual)
+                }
+            }
+            Ok(meta) if meta.file_type().is_symlink() && path.is_dir() => {
+                DirectoryStatus::Symlink(path.read_link()?)
+            }
+            Ok(_) => DirectoryStatus::Wrong,
+            Err(_) => DirectoryStatus::Absent,
+        };
+        Ok(Directory { path, mode, status })
+    }
+
+    pub fn create(&self) -> Result<()> {
+        info!("Creating directory {:?} with mode {}", self.path, self.mode);
+        fs::create_dir(&self.path)?;
+        fs::set_permissions(&self.path, self.mode.into())?;
+        Ok(())
+    }
+
+    pub fn repair(
+        &self,
+        wrong_behaviour: fn(&PathBuf) -> Result<RepairAction>,
+    ) -> Result<RepairResult> {
+        let result = match self.status {
+            DirectoryStatus::Wrong => match wrong_behaviour(&self.path)? {
+                RepairAction::Skip => {
+                    warn!("Skipping file {:?}", self.path);
+                    RepairResult::Skipped
+                }
+                RepairAction::Delete => {
+                    info!("Deleting file {:?}", self.path);
+                    fs::remove_file(&self.path)?;
+                    self.create()?;
+                    RepairResult::Successful
+                }
+            },
+            DirectoryStatus::Absent => {
+                self.create()?;
+                RepairResult::Successful
+            }
+            DirectoryStatus::Mode(_) => {
+                info!("Setting mode of {:?} to {}", self.path, self.mode);
+                // setgid and sticky bits are not managed and kept as they are
+                let special = self.path.symlink_metadata()?.permissions().mode() & SPECIAL_MASK;
+                let mode = self.mode.bits() as u32 | special;
+                fs::set_permissions(&self.path, fs::Permissions::from_mode(mode))?;
+                RepairResult::Successful
+            }
+            DirectoryStatus::Symlink(ref target) => {
+                warn!(
+                    "Skipping {:?}, it is a symlink to {:?} and has to be replaced manually",
+                    self.path, target
+                );
+                RepairResult::Skipped
+            }
+            DirectoryStatus::Ok => RepairResult::Successful,
+        };
+
+        Ok(result)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
 pub struct Dotfiles {
     version: Option<i64>, // always 1
     files: Option<Vec<PathBuf>>,
     executables: Option<Vec<PathBuf>>,
     deleted: Option<Vec<PathBuf>>,
+    // tracked directories that are mirrored as real directories with symlinked leaves, only
+    // changed by the fold and unfold commands, never automatically
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unfolded: Option<Vec<PathBuf>>,
+    // modes of parent directories in the home directory, applied when they are created
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    parent_modes: BTreeMap<PathBuf, Perms>,
+    // directories in the home directory that have to exist with the given mode
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    dirs: BTreeMap<PathBuf, Perms>,
+    // symlinks in the home directory that are tracked by their target
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    links: BTreeMap<PathBuf, PathBuf>,
+    // entries that intentionally override the same entries of lower-priority layers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    overrides: Vec<PathBuf>,
+    // other manifests, relative to this one, whose entries are managed alongside
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    include: Vec<PathBuf>,
+    // gitignore patterns for content that should not be tracked inside any tracked directory
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    ignore: Vec<String>,
+    // gitignore patterns that only apply inside the given tracked directory
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    entry_ignore: BTreeMap<PathBuf, Vec<String>>,
+    // owner and group of the content of tracked files, or of declared directories
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    owners: BTreeMap<PathBuf, Owner>,
 }
 
 impl Dotfiles {
@@ -146,6 +315,65 @@ impl Dotfiles {
             deleted,
             executables,
             version: Some(1),
+            unfolded: None,
+            parent_modes: BTreeMap::new(),
+            dirs: BTreeMap::new(),
+            links: BTreeMap::new(),
+            overrides: vec![],
+            include: vec![],
+            ignore: vec![],
+            entry_ignore: BTreeMap::new(),
+            owners: BTreeMap::new(),
+        }
+    }
+
+    fn updated(
+        &self,
+        files: Vec<PathBuf>,
+        executables: Vec<PathBuf>,
+        deleted: Vec<PathBuf>,
+    ) -> Dotfiles {
+        Dotfiles {
+            files: Some(files),
+            executables: Some(executables),
+            deleted: Some(deleted),
+            ..self.canonicalize()
+        }
+        .pruned()
+    }
+
+    // drops everything that is recorded about entries that are gone
+    fn pruned(self) -> Dotfiles {
+        let files = self.get_files();
+        let entries = self.get_entries();
+        let owners = self
+            .get_owners()
+            .into_iter()
+            .filter(|(entry, _)| files.contains(entry) || self.dirs.contains_key(entry))
+            .collect();
+        let unfolded = self.unfolded.as_ref().map(|unfolded| {
+            unfolded
+                .iter()
+                .filter(|f| files.contains(f))
+                .cloned()
+                .collect()
+        });
+        let entry_ignore = self
+            .get_entry_ignore()
+            .into_iter()
+            .filter(|(entry, _)| files.contains(entry))
+            .collect();
+        let overrides = self
+            .get_overrides()
+            .into_iter()
+            .filter(|entry| entries.contains(entry))
+            .collect();
+        Dotfiles {
+            owners,
+            unfolded,
+            entry_ignore,
+            overrides,
+            ..self
         }
     }
 
@@ -170,12 +398,97 @@ impl Dotfiles {
         }
     }
 
+    pub fn get_unfolded(&self) -> Vec<PathBuf> {
+        match self.unfolded {
+            Some(ref unfolded) => unfolded.clone(),
+            None => vec![],
+        }
+    }
+
+    pub fn get_parent_modes(&self) -> BTreeMap<PathBuf, Perms> {
+        self.parent_modes.clone()
+    }
+
+    pub fn get_dirs(&self) -> BTreeMap<PathBuf, Perms> {
+        self.dirs.clone()
+    }
+
+    pub fn get_links(&self) -> BTreeMap<PathBuf, PathBuf> {
+        self.links.clone()
+    }
+
+    pub fn get_overrides(&self) -> Vec<PathBuf> {
+        self.overrides.clone()
+    }
+
+    pub fn get_include(&self) -> Vec<PathBuf> {
+        self.include.clone()
+    }
+
+    pub fn get_ignore(&self) -> Vec<String> {
+        self.ignore.clone()
+    }
+
+    pub fn get_entry_ignore(&self) -> BTreeMap<PathBuf, Vec<String>> {
+        self.entry_ignore.clone()
+    }
+
+    pub fn get_owners(&self) -> BTreeMap<PathBuf, Owner> {
+        self.owners.clone()
+    }
+
+    pub fn get_entries(&self) -> Vec<PathBuf> {
+        let mut entries = self.get_files();
+        entries.extend(self.links.keys().cloned());
+        entries.extend(self.dirs.keys().cloned());
+        entries
+    }
+
+    pub fn without(&self, entries: &[PathBuf]) -> Dotfiles {
+        let keep = |entry: &PathBuf| !entries.contains(entry);
+        Dotfiles {
+            files: Some(self.get_files().into_iter().filter(keep).collect()),
+            executables: Some(self.get_executables().into_iter().filter(keep).collect()),
+            unfolded: self
+                .unfolded
+                .as_ref()
+                .map(|unfolded| unfolded.iter().filter(|u| keep(u)).cloned().collect()),
+            dirs: self
+                .get_dirs()
+                .into_iter()
+                .filter(|(d, _)| keep(d))
+                .collect(),
+            links: self
+                .get_links()
+                .into_iter()
+                .filter(|(l, _)| keep(l))
+                .collect(),
+            owners: self
+                .get_owners()
+                .into_iter()
+                .filter(|(o, _)| keep(o))
+                .collect(),
+            ..self.canonicalize()
+        }
+    }
+
     pub fn canonicalize(&self) -> Dotfiles {
-        Dotfiles::new(
-            Some(self.get_files()),
-            Some(self.get_executables()),
-            Some(self.get_deleted()),
-        )
+        Dotfiles {
+            owners: self.get_owners(),
+            ignore: self.get_ignore(),
+            entry_ignore: self.get_entry_ignore(),
+            include: self.get_include(),
+            overrides: self.get_overrides(),
+            links: self.get_links(),
+            dirs: self.get_dirs(),
+            unfolded: self.unfolded.clone(),
+            parent_modes: self.get_parent_modes(),
+            ..Dotfiles::new(
+                Some(self.get_files()),
+                Some(self.get_executables()),
+                Some(self.get_deleted()),
+            )
+        }
     }
 
     pub fn get_absent_files(&self, contents: &Path) -> Vec<PathBuf> {
@@ -186,19 +499,375 @@ impl Dotfiles {
         unexpected_files(contents, &self.get_deleted(), false)
     }
 
-    pub fn get_symlinks(&self, contents: &Path, home: &Path) -> HashMap<PathBuf, Symlink> {
-        self.get_files()
-            .iter()
-            .map(|dotfile| (dotfile.clone(), Symlink::get(contents, home, dotfile)))
+    pub fn get_overlapping_files(&self) -> Vec<(PathBuf, PathBuf)> {
+        let files = self.get_files();
+        let mut overlapping = vec![];
+        for parent in &files {
+            for child in &files {
+                if child != parent && child.starts_with(parent) {
+                    overlapping.push((parent.clone(), child.clone()));
+                }
+            }
+        }
+        overlapping
+    }
+
+    // the ignore patterns that apply inside the tracked directory `dotfile`
+    fn get_ignore_matcher(&self, contents: &Path, dotfile: &Path) -> Result<Gitignore> {
+        let mut builder = GitignoreBuilder::new(contents.join(dotfile));
+        let patterns = self.entry_ignore.get(dotfile).into_iter().flatten();
+        for pattern in self.ignore.iter().chain(patterns) {
+            builder.add_line(None, pattern)?;
+        }
+        Ok(builder.build()?)
+    }
+
+    // relative to `base`, ignored directories are listed as a whole
+    fn find_ignored(&self, base: &Path, dotfile: &Path) -> Result<Vec<PathBuf>> {
+        fn go(
+            base: &Path,
+            dir: &Path,
+            matcher: &Gitignore,
+            ignored: &mut Vec<PathBuf>,
+        ) -> Result<()> {
+            let mut children = fs::read_dir(base.join(dir))?
+                .map(|entry| Ok(dir.join(entry?.file_name())))
+                .collect::<Result<Vec<PathBuf>>>()?;
+            children.sort();
+            for child in children {
+                let path = base.join(&child);
+                let is_dir = path.symlink_metadata()?.is_dir();
+                if matcher.matched(&path, is_dir).is_ignore() {
+                    ignored.push(child);
+                } else if is_dir {
+                    go(base, &child, matcher, ignored)?;
+                }
+            }
+            Ok(())
+        }
+
+        let mut ignored = vec![];
+        if self.ignore.is_empty() && !self.entry_ignore.contains_key(dotfile) {
+            return Ok(ignored);
+        }
+        let matcher = self.get_ignore_matcher(base, dotfile)?;
+        go(base, dotfile, &matcher, &mut ignored)?;
+        Ok(ignored)
+    }
+
+    // ignored directories are listed as a whole
+    pub fn get_ignored_files(&self, contents: &Path) -> Result<Vec<PathBuf>> {
+        let mut ignored = vec![];
+        for dotfile in self.get_files() {
+            if contents.join(&dotfile).is_dir() {
+                ignored.extend(self.find_ignored(contents, &dotfile)?);
+            }
+        }
+        Ok(ignored)
+    }
+
+    pub fn get_orphaned_files(&self, contents: &Path) -> Result<Vec<PathBuf>> {
+        fn go(
+            contents: &Path,
+            dir: &Path,
+            known: &[PathBuf],
+            orphans: &mut Vec<PathBuf>,
+        ) -> Result<()> {
+            for entry in fs::read_dir(contents.join(dir))? {
+                let relative = dir.join(entry?.file_name());
+                if known.contains(&relative) {
+                    continue;
+                }
+                let is_dir = contents.join(&relative).symlink_metadata()?.is_dir();
+                if is_dir && known.iter().any(|file| file.starts_with(&relative)) {
+                    go(contents, &relative, known, orphans)?;
+                } else {
+                    orphans.push(relative);
+                }
+            }
+            Ok(())
+        }
+
+        let mut orphans = vec![];
+        if contents.is_dir() {
+            let mut known = self.get_files();
+            known.append(&mut self.get_deleted());
+            go(contents, Path::new(""), &known, &mut orphans)?;
+        }
+        orphans.sort();
+        Ok(orphans)
+    }
+
+    pub fn get_directories(&self, home: &Path) -> Result<BTreeMap<PathBuf, Directory>> {
+        self.get_dirs()
+            .into_iter()
+            .map(|(dir, mode)| {
+                let directory = Directory::get(home, &dir, mode)?;
+                Ok((dir, directory))
+            })
+            .collect()
+    }
+
+    pub fn get_link_symlinks(&self, home: &Path) -> BTreeMap<PathBuf, Symlink> {
+        self.get_links()
+            .into_iter()
+            .map(|(link, target)| {
+                let symlink = Symlink::at(target, home.join(&link));
+                (link, symlink)
+            })
             .collect()
     }
 
+    pub fn get_symlinks(&self, contents: &Path, home: &Path) -> Result<HashMap<PathBuf, Symlink>> {
+        let unfolded = self.get_unfolded();
+        let mut symlinks = HashMap::new();
+        for dotfile in self.get_files() {
+            if unfolded.contains(&dotfile) {
+                let root = contents.join(&dotfile);
+                for leaf in walk(&root)? {
+                    if !root.join(&leaf).symlink_metadata()?.is_dir() {
+                        let leaf = dotfile.join(leaf);
+                        symlinks.insert(leaf.clone(), Symlink::get(contents, home, &leaf));
+                    }
+                }
+            } else {
+                let symlink = Symlink::get(contents, home, &dotfile);
+                symlinks.insert(dotfile, symlink);
+            }
+        }
+        Ok(symlinks)
+    }
+
+    fn create_parents(&self, home: &Path, dotfile: &Path) -> Result<()> {
+        let modes = self.get_parent_modes();
+        let dirs = self.get_dirs();
+        let mut ancestors = dotfile.ancestors().skip(1).collect::<Vec<_>>();
+        ancestors.reverse();
+        for ancestor in ancestors {
+            let path = home.join(ancestor);
+            if ancestor.as_os_str().is_empty() || path.symlink_metadata().is_ok() {
+                continue;
+            }
+            info!("Creating directory {:?}", path);
+            fs::create_dir(&path)?;
+            if let Some(mode) = modes.get(ancestor).or_else(|| dirs.get(ancestor)) {
+                fs::set_permissions(&path, (*mode).into())?;
+            }
+        }
+        Ok(())
+    }
+
+    // unfolded directories are listed by their leaves
+    pub fn get_states(&self, config: &Config) -> Result<BTreeMap<PathBuf, &'static str>> {
+        fn symlink_state(symlink: &Symlink) -> &'static str {
+            match symlink.status {
+                SymlinkStatus::Ok => "ok",
+                SymlinkStatus::Absent(_) => "missing",
+                SymlinkStatus::Wrong => "wrong",
+            }
+        }
+
+        let home = config.get_home()?;
+        let mut states = BTreeMap::new();
+        for (file, symlink) in self.get_symlinks(&config.contents(), &home)? {
+            states.insert(file, symlink_state(&symlink));
+        }
+        for (link, symlink) in self.get_link_symlinks(&home) {
+            states.insert(link, symlink_state(&symlink));
+        }
+        for (dir, directory) in self.get_directories(&home)? {
+            let state = match directory.status {
+                DirectoryStatus::Ok => "ok",
+                DirectoryStatus::Absent => "missing",
+                DirectoryStatus::Wrong => "wrong",
+                DirectoryStatus::Mode(_) => "mode",
+                DirectoryStatus::Symlink(_) => "symlink",
+            };
+            states.insert(dir, state);
+        }
+        Ok(states)
+    }
+
+    // paths that resolve to the content are no conflicts
+    pub fn get_conflicts(&self, config: &Config) -> Result<Vec<PathBuf>> {
+        let home = config.get_home()?;
+        let contents = config.contents();
+        let mut conflicts = vec![];
+        for directory in self.get_directories(&home)?.into_values() {
+            if let DirectoryStatus::Wrong = directory.status {
+                conflicts.push(directory.path);
+            }
+        }
+        let unfolded = self.get_unfolded();
+        for dotfile in &unfolded {
+            let path = home.join(dotfile);
+            if let Ok(meta) = path.symlink_metadata() {
+                let folded =
+                    meta.file_type().is_symlink() && path.read_link()? == contents.join(dotfile);
+                if !folded && !meta.is_dir() {
+                    conflicts.push(path);
+                }
+            }
+        }
+        let symlinks = self.get_symlinks(&contents, &home)?;
+        for symlink in symlinks
+            .into_values()
+            .chain(self.get_link_symlinks(&home).into_values())
+        {
+            if let SymlinkStatus::Wrong = symlink.status {
+                let resolved = symlink.path.canonicalize().ok();
+                if resolved.is_none() || resolved != symlink.expected.canonicalize().ok() {
+                    conflicts.push(symlink.path);
+                }
+            }
+        }
+        conflicts.sort();
+        Ok(conflicts)
+    }
+
+    // tracked links are symlinks on their own and stay in place
+    pub fn eject(&self, config: &Config) -> Result<()> {
+        let home = config.get_home()?;
+        let mut symlinks = self
+            .get_symlinks(&config.contents(), &home)?
+            .into_iter()
+            .collect::<Vec<_>>();
+        symlinks.sort_by(|(left, _), (right, _)| left.cmp(right));
+        for (_, symlink) in symlinks {
+            symlink.eject()?;
+        }
+        Ok(())
+    }
+
+    pub fn rebase_links(&self, from: &Path, to: &Path) -> Dotfiles {
+        let links = self
+            .get_links()
+            .into_iter()
+            .map(|(link, target)| match target.strip_prefix(from) {
+                Ok(rest) => (link, to.join(rest)),
+                Err(_) => (link, target),
+            })
+            .collect();
+        Dotfiles {
+            links,
+            ..self.canonicalize()
+        }
+    }
+
+    // relative includes stay relative when this manifest moves from `base` to `new_base`
+    pub fn rebase_include(&self, base: &Path, new_base: &Path, from: &Path, to: &Path) -> Dotfiles {
+        let include = self
+            .get_include()
+            .into_iter()
+            .map(|path| {
+                let resolved = paths::normalize(&base.join(&path));
+                let resolved = match resolved.strip_prefix(from) {
+                    Ok(rest) => to.join(rest),
+                    Err(_) => resolved,
+                };
+                if path.is_absolute() {
+                    resolved
+                } else {
+                    paths::relative_to(new_base, &resolved)
+                }
+            })
+            .collect();
+        Dotfiles {
+            include,
+            ..self.canonicalize()
+        }
+    }
+
+    pub fn unlink(&self, config: &Config) -> Result<()> {
+        let home = config.get_home()?;
+        let symlinks = self.get_symlinks(&config.contents(), &home)?;
+        for symlink in symlinks
+            .into_values()
+            .chain(self.get_link_symlinks(&home).into_values())
+        {
+            if let SymlinkStatus::Ok = symlink.status {
+                info!("Removing symlink {:?}", symlink.path);
+                fs::remove_file(&symlink.path)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn repoint(&self, config: &Config, from: &Path, to: &Path) -> Result<()> {
+        let home = config.get_home()?;
+        for symlink in self.get_symlinks(&config.contents(), &home)?.into_values() {
+            if let Ok(actual) = symlink.path.read_link() {
+                if let Ok(rest) = actual.strip_prefix(from) {
+                    let target = to.join(rest);
+                    info!("Pointing {:?} to {:?}", symlink.path, target);
+                    fs::remove_file(&symlink.path)?;
+                    unix::symlink(target, &symlink.path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn prepare_unfolded(
+        &self,
+        contents: &Path,
+        home: &Path,
+        dotfile: &PathBuf,
+        wrong_behaviour: fn(&PathBuf) -> Result<RepairAction>,
+    ) -> Result<RepairResult> {
+        let path = home.join(dotfile);
+        let expected = contents.join(dotfile);
+        if let Ok(meta) = path.symlink_metadata() {
+            if meta.file_type().is_symlink() && path.read_link()? == expected {
+                info!("Unfolding {:?}", path);
+                fs::remove_file(&path)?;
+            } else if !meta.is_dir() {
+                match wrong_behaviour(&path)? {
+                    RepairAction::Skip => {
+                        warn!("Skipping unfolded directory {:?}", path);
+                        return Ok(RepairResult::Skipped);
+                    }
+                    RepairAction::Delete => {
+                        info!("Deleting file {:?}", path);
+                        fs::remove_file(&path)?;
+                    }
+                }
+            }
+        }
+
+        self.create_parents(home, dotfile)?;
+        if path.symlink_metadata().is_err() {
+            fs::create_dir(&path)?;
+        }
+        for entry in walk(&expected)? {
+            if expected.join(&entry).symlink_metadata()?.is_dir() {
+                fs::create_dir_all(path.join(entry))?;
+            }
+        }
+        Ok(RepairResult::Successful)
+    }
+
+    // returns whether the target already contained a manifest
+    pub fn scaffold(config: &Config) -> Result<bool> {
+        let existing = config.dotfiles().is_file();
+        if !config.contents().is_dir() {
+            info!("Creating {:?}", config.contents());
+            fs::create_dir(config.contents())?;
+        }
+        if !existing {
+            info!("Creating empty manifest {:?}", config.dotfiles());
+            Dotfiles::new(Some(vec![]), Some(vec![]), Some(vec![])).save(config)?;
+        }
+        Ok(existing)
+    }
+
     pub fn load(config: &Config) -> Result<Dotfiles> {
         let mut contents = String::new();
         OpenOptions::new()
             .write(true)
             .read(true)
             .create(true)
+            .truncate(false)
             .open(config.dotfiles())?
             .read_to_string(&mut contents)?;
         let toml = toml::from_str::<Value>(contents.as_ref())?;
@@ -222,18 +891,84 @@ impl Dotfiles {
         }
     }
 
-    pub fn save(&self, config: &Config) -> Result<()> {
-        let contents = toml::to_string(&self.canonicalize())?;
-        OpenOptions::new()
-            .truncate(true)
-            .write(true)
-            .create(true)
-            .open(config.dotfiles())?
-            .write_all(contents.as_bytes())?;
+    // included manifests are resolved relative to the including one, recursively
+    pub fn load_all(config: &Config) -> Result<Vec<(Config, Dotfiles)>> {
+        let mut loaded = vec![];
+        Dotfiles::load_included(config.clone(), &mut vec![], &mut loaded)?;
+        Ok(loaded)
+    }
+
+    // in system scope, the target, the manifest and the content must be owned by root and
+    // writable by nobody else before anything is read, so that nothing a user can write is ever
+    // applied to the system, not even by a repair
+    fn check_system_ownership(config: &Config) -> Result<()> {
+        info!("Checking ownership of {:?}", config.target());
+        perm::check_root_owned_ancestors(&config.target())?;
+        let manifest = config.dotfiles();
+        if let Some(dir) = manifest.parent() {
+            perm::check_root_owned_ancestors(dir)?;
+        }
+        perm::check_root_owned(&manifest)?;
+        if config.contents().symlink_metadata().is_ok() {
+            perm::check_root_owned(&config.contents())?;
+        }
+        Ok(())
+    }
+
+    fn load_included(
+        config: Config,
+        stack: &mut Vec<PathBuf>,
+        loaded: &mut Vec<(Config, Dotfiles)>,
+    ) -> Result<()> {
+        if config.is_system_scope() {
+            Dotfiles::check_system_ownership(&config)?;
+        }
+        let dotfiles = Dotfiles::load(&config)?;
+        let manifest = config.dotfiles().canonicalize()?;
+        let base = config.target();
+        let include = dotfiles.get_include();
+        stack.push(manifest);
+        loaded.push((config.clone(), dotfiles));
+
+        for path in include {
+            let path = base.join(path);
+            if !path.is_file() {
+                Err(anyhow!("Included manifest {:?} does not exist", path))?
+            }
+            let path = path.canonicalize()?;
+            if let Some(start) = stack.iter().position(|other| *other == path) {
+                let cycle = stack[start..]
+                    .iter()
+                    .chain(std::iter::once(&path))
+                    .map(|manifest| format!("{:?}", manifest))
+                    .collect::<Vec<_>>();
+                Err(anyhow!("Cyclic include: {}", cycle.join(" -> ")))?
+            }
+            for (other, _) in loaded.iter() {
+                if other.dotfiles().canonicalize()? == path {
+                    Err(anyhow!("{:?} is included more than once", path))?
+                }
+                if other.target().canonicalize()?.as_path() == path.parent().unwrap() {
+                    Err(anyhow!(
+                        "{:?} and {:?} share the contents directory {:?}",
+                        other.dotfiles(),
+                        path,
+                        other.contents()
+                    ))?
+                }
+            }
+            Dotfiles::load_included(config.include(path), stack, loaded)?;
+        }
+
+        stack.pop();
         Ok(())
     }
 
-    pub fn check(&self, config: &Config) -> Result<()> {
+    pub fn save(&self, config: &Config) -> Result<()> {
+        write_atomically(&config.dotfiles(), &toml::to_string(&self.canonicalize())?)
+    }
+
+    pub fn check_contents(&self, config: &Config) -> Result<()> {
         info!("Checking consistency");
         let files = self.get_files();
         let deleted = self.get_deleted();
@@ -248,28 +983,157 @@ impl Dotfiles {
         if let Some(f) = executables.iter().find(|f| !files.contains(f)) {
             Err(anyhow!("Unknown file {:?} is marked executable", f))?
         }
-
-        info!("Consistent.");
-
-        info!("Checking for absent content in {:?}", config.contents());
-        let absent_contents = self.get_absent_files(config.contents().as_path());
-        if absent_contents.is_empty() {
-            info!("No absent content.")
-        } else {
-            Err(anyhow!("Absent content: {:?}", absent_contents))?
-        }
-
-        info!("Checking for spurious content in {:?}", config.contents());
-        let spurious_contents = self.get_spurious_files(config.contents().as_path());
-        if spurious_contents.is_empty() {
-            info!("No spurious content.")
-        } else {
-            Err(anyhow!("Spurious content: {:?}", spurious_contents))?
+        if let Some(dir) = self.parent_modes.keys().find(|dir| !dir.is_relative()) {
+            Err(anyhow!("Parent mode for {:?} must be a relative path", dir))?
+        }
+        for dir in self.dirs.keys() {
+            if !dir.is_relative() {
+                Err(anyhow!("Directory {:?} must be a relative path", dir))?
+            }
+            if let Some(f) = files.iter().find(|f| dir.starts_with(f)) {
+                Err(anyhow!(
+                    "Directory {:?} overlaps with the tracked {:?}",
+                    dir,
+                    f
+                ))?
+            }
+        }
+        for link in self.links.keys() {
+            if !link.is_relative() {
+                Err(anyhow!("Link {:?} must be a relative path", link))?
+            }
+            if let Some(f) = files.iter().find(|f| paths::overlaps(f, link)) {
+                Err(anyhow!("Link {:?} overlaps with the tracked {:?}", link, f))?
+            }
+            if self.dirs.contains_key(link) {
+                Err(anyhow!("Link {:?} is also declared as directory", link))?
+            }
+        }
+        let entries = self.get_entries();
+        if let Some(o) = self.overrides.iter().find(|o| !entries.contains(o)) {
+            Err(anyhow!("Unknown file {:?} is marked as override", o))?
+        }
+        let unfolded = self.get_unfolded();
+        if !is_unique(&unfolded) {
+            Err(anyhow!("Duplicate unfolded directories"))?
+        }
+        if let Some(f) = unfolded.iter().find(|f| !files.contains(f)) {
+            Err(anyhow!("Unknown file {:?} is marked unfolded", f))?
+        }
+        if let Some(f) = self.entry_ignore.keys().find(|f| !files.contains(f)) {
+            Err(anyhow!("Unknown file {:?} has ignore patterns", f))?
+        }
+        if let Some(o) = self
+            .owners
+            .keys()
+            .find(|o| !files.contains(o) && !self.dirs.contains_key(*o))
+        {
+            Err(anyhow!("Unknown file {:?} has an owner", o))?
+        }
+        // ensures that all patterns are valid
+        self.get_ignore_matcher(&config.contents(), Path::new(""))?;
+        for dotfile in self.entry_ignore.keys() {
+            self.get_ignore_matcher(&config.contents(), dotfile)?;
+        }
+
+        let overlapping = self.get_overlapping_files();
+        if !overlapping.is_empty() {
+            Err(anyhow!(
+                "Overlapping files (parent, child), see merge: {:?}",
+                overlapping
+            ))?
+        }
+
+        info!("Consistent.");
+
+        info!("Checking for absent content in {:?}", config.contents());
+        let absent_contents = self.get_absent_files(config.contents().as_path());
+        if absent_contents.is_empty() {
+            info!("No absent content.")
+        } else {
+            Err(anyhow!("Absent content: {:?}", absent_contents))?
         }
 
+        info!("Checking for spurious content in {:?}", config.contents());
+        let spurious_contents = self.get_spurious_files(config.contents().as_path());
+        if spurious_contents.is_empty() {
+            info!("No spurious content.")
+        } else {
+            Err(anyhow!("Spurious content: {:?}", spurious_contents))?
+        }
+
+        info!("Checking for orphaned content in {:?}", config.contents());
+        let orphaned_contents = self.get_orphaned_files(config.contents().as_path())?;
+        if orphaned_contents.is_empty() {
+            info!("No orphaned content.")
+        } else {
+            Err(anyhow!(
+                "Orphaned content (neither listed nor deleted, see adopt-orphans): {:?}",
+                orphaned_contents
+            ))?
+        }
+
+        Ok(())
+    }
+
+    pub fn check_home(&self, config: &Config) -> Result<()> {
+        let files = self.get_files();
+        let executables = self.get_executables();
+        let unfolded = self.get_unfolded();
         let home = config.get_home()?;
+        info!("Checking for directories in {:?}", home);
+        let directories = self.get_directories(&home)?;
+        for (dir, directory) in &directories {
+            match directory.status {
+                DirectoryStatus::Ok => {}
+                DirectoryStatus::Absent => Err(anyhow!(
+                    "{:?} does not exist, expected directory with mode {}",
+                    dir,
+                    directory.mode
+                ))?,
+                DirectoryStatus::Wrong => Err(anyhow!(
+                    "{:?} is not a directory, expected directory with mode {}",
+                    dir,
+                    directory.mode
+                ))?,
+                DirectoryStatus::Mode(actual) => Err(anyhow!(
+                    "Mode mismatch: expected {:?} as {}, but actually is {}",
+                    dir,
+                    directory.mode,
+                    actual
+                ))?,
+                DirectoryStatus::Symlink(ref target) => Err(anyhow!(
+                    "{:?} is a symlink to {:?}, expected directory with mode {}",
+                    dir,
+                    target,
+                    directory.mode
+                ))?,
+            }
+        }
+        info!("{} directories correct.", directories.len());
+
+        info!("Checking for unfolded directories in {:?}", home);
+        for dotfile in &unfolded {
+            if !config.contents().join(dotfile).is_dir() {
+                Err(anyhow!(
+                    "{:?} is marked unfolded, but is not a directory",
+                    dotfile
+                ))?
+            }
+            let meta = home.join(dotfile).symlink_metadata();
+            if meta
+                .map(|meta| meta.file_type().is_symlink())
+                .unwrap_or(false)
+            {
+                Err(anyhow!(
+                    "{:?} is marked unfolded, but is a symlink instead of a real directory",
+                    dotfile
+                ))?
+            }
+        }
+
         info!("Checking for symlinks and executable flag in {:?}", home);
-        let symlinks = self.get_symlinks(config.contents().as_path(), home.as_path());
+        let symlinks = self.get_symlinks(config.contents().as_path(), home.as_path())?;
         for (dotfile, symlink) in &symlinks {
             match symlink.status {
                 SymlinkStatus::Wrong => Err(anyhow!(
@@ -285,7 +1149,9 @@ impl Dotfiles {
                 ))?,
                 SymlinkStatus::Ok => {
                     // now let's see if we're pointing to a file to check executability
-                    if symlink.expected.is_file() {
+                    if !files.contains(dotfile) {
+                        // leaf of an unfolded directory, executability is not tracked
+                    } else if symlink.expected.is_file() {
                         let actual = Executable::get(&symlink.expected)?;
                         let expected = Executable::from(executables.contains(dotfile));
                         if actual != expected {
@@ -305,33 +1171,109 @@ impl Dotfiles {
         }
         info!("{} symlink(s) correct.", symlinks.len());
 
+        info!("Checking for tracked links in {:?}", home);
+        let links = self.get_link_symlinks(&home);
+        for (link, symlink) in &links {
+            match symlink.status {
+                SymlinkStatus::Ok => {}
+                SymlinkStatus::Wrong => Err(anyhow!(
+                    "{:?} is not a symlink or symlink with wrong target, expected: {:?}",
+                    link,
+                    symlink.expected
+                ))?,
+                SymlinkStatus::Absent(ref err) => Err(anyhow!(
+                    "{:?} does not exist, expected symbolic link to {:?} ({:?})",
+                    link,
+                    symlink.expected,
+                    err
+                ))?,
+            }
+        }
+        info!("{} link(s) correct.", links.len());
+
+        info!("Checking for owners");
+        if let Some((path, expected, actual)) = self.get_owner_mismatches(config)?.first() {
+            Err(anyhow!(
+                "Owner mismatch: expected {:?} as {}, but actually is {}",
+                path,
+                expected,
+                actual
+            ))?
+        }
+        info!("{} owner(s) correct.", self.owners.len());
+
         Ok(())
     }
 
-    pub fn track(
+    // a declared directory itself, or the content of a tracked file and its children
+    pub fn get_owned_paths(
+        &self,
+        home: &Path,
+        contents: &Path,
+        entry: &Path,
+    ) -> Result<Vec<PathBuf>> {
+        let mut paths = vec![];
+        if self.dirs.contains_key(entry) {
+            paths.push(home.join(entry));
+        } else {
+            let root = contents.join(entry);
+            if root.symlink_metadata().is_ok_and(|meta| meta.is_dir()) {
+                paths.extend(walk(&root)?.into_iter().map(|path| root.join(path)));
+            }
+            paths.push(root);
+        }
+        // absent entries are reported elsewhere
+        paths.retain(|path| path.symlink_metadata().is_ok());
+        Ok(paths)
+    }
+
+    pub fn get_owner_mismatches(&self, config: &Config) -> Result<Vec<(PathBuf, Owner, Owner)>> {
+        let home = config.get_home()?;
+        let contents = config.contents();
+        let mut mismatches = vec![];
+        for (entry, owner) in &self.owners {
+            for path in self.get_owned_paths(&home, &contents, entry)? {
+                let actual = Owner::get(&path)?;
+                if !owner.matches(&actual) {
+                    mismatches.push((path, *owner, actual));
+                }
+            }
+        }
+        Ok(mismatches)
+    }
+
+    pub fn check_track(
         &self,
         config: &Config,
         file: &PathBuf,
-        validate_relative: fn(&PathBuf) -> Result<()>,
-    ) -> Result<Dotfiles> {
-        let file_type = file.symlink_metadata()?.file_type();
-        if file_type.is_symlink() {
-            Err(anyhow!("Cannot track {:?} because it is a symlink", file))?
+        validate_relative: fn(&Config, &Path) -> Result<()>,
+    ) -> Result<PathBuf> {
+        if file.symlink_metadata()?.file_type().is_symlink() {
+            return self.check_track_link(config, file, validate_relative);
         }
 
         let file = file.canonicalize()?;
-        let home = config.get_home()?;
-        if !file.starts_with(home.clone()) {
+        if let Ok(inner) = file.strip_prefix(config.contents()) {
             Err(anyhow!(
-                "Cannot track {:?} because it is not in the home directory {:?}",
-                file,
-                home
+                "Cannot track {:?} because it is inside a tracked directory{}",
+                inner,
+                match self.get_files().iter().find(|f| inner.starts_with(f)) {
+                    Some(parent) => format!(" {:?}", parent),
+                    None => String::new(),
+                }
             ))?
         }
+        let home = config.get_home()?;
+        let relative = result_from_option(
+            paths::home_relative(&home, &file),
+            format!(
+                "Cannot track {:?} because it is not in the home directory {:?}",
+                file, home
+            ),
+        )?;
 
-        let mut files = self.get_files();
-        let relative = paths::relative_to(&home, &file);
-        validate_relative(&relative)?;
+        let files = self.get_files();
+        validate_relative(config, &relative)?;
         if files.contains(&relative) {
             Err(anyhow!(
                 "Cannot track {:?} because it is already tracked",
@@ -339,77 +1281,274 @@ impl Dotfiles {
             ))?
         }
 
-        let deleted = self.get_deleted();
-        if deleted.contains(&relative) {
+        if let Some(other) = files.iter().find(|f| paths::overlaps(f, &relative)) {
+            Err(anyhow!(
+                "Cannot track {:?} because it overlaps with the tracked {:?}",
+                relative,
+                other
+            ))?
+        }
+
+        if self.get_deleted().contains(&relative) {
             Err(anyhow!(
                 "Cannot track {:?} because it has been deleted",
                 file
             ))?
         }
+        Ok(relative)
+    }
+
+    pub fn track(
+        &self,
+        config: &Config,
+        file: &PathBuf,
+        validate_relative: fn(&Config, &Path) -> Result<()>,
+    ) -> Result<Dotfiles> {
+        let relative = self.check_track(config, file, validate_relative)?;
+        if file.symlink_metadata()?.file_type().is_symlink() {
+            let mut links = self.get_links();
+            let target = file.read_link()?;
+            info!("Tracking link {:?} to {:?}", relative, target);
+            links.insert(relative, target);
+            return Ok(Dotfiles {
+                links,
+                ..self.canonicalize()
+            });
+        }
 
-        if file_type.is_file() {
+        let file = file.canonicalize()?;
+        let ignored = if file.is_dir() {
+            self.find_ignored(&config.get_home()?, &relative)?
+        } else {
+            vec![]
+        };
+        if file.is_file() {
             info!("Tracking {:?}", relative);
         } else {
             info!("Tracking {:?} and all its children", relative);
         }
 
-        let mut dest = config.contents();
-        dest.push(relative.clone());
-        let content_path = dest.clone();
-        dest.pop();
-        fs::create_dir_all(dest.clone())?;
-        fs_extra::move_items(&[file.clone()], dest, &CopyOptions::new())?;
+        let content_path = config.contents().join(&relative);
+        if let Some(parent) = content_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut files = self.get_files();
+        files.push(relative.clone());
+        if ignored.is_empty() {
+            transfer::move_item(&file, &content_path)?;
+            unix::symlink(content_path, file)?;
+            return Ok(self.updated(files, self.get_executables(), self.get_deleted()));
+        }
 
-        unix::symlink(content_path, file)?;
+        info!("Leaving ignored {:?} in the home directory", ignored);
+        let ignored = ignored
+            .iter()
+            .map(|path| path.strip_prefix(&relative).unwrap())
+            .collect::<Vec<_>>();
+        Self::track_unfolded(&file, &content_path, &ignored)?;
+        let mut unfolded = self.get_unfolded();
+        unfolded.push(relative);
+        Ok(Dotfiles {
+            unfolded: Some(unfolded),
+            ..self.updated(files, self.get_executables(), self.get_deleted())
+        })
+    }
 
-        files.push(relative);
-        Ok(Dotfiles::new(
-            Some(files),
-            Some(self.get_executables()),
-            Some(deleted),
-        ))
+    // moves the content of the directory `file` to `content` leaf by leaf and links the leaves
+    // like in an unfolded directory, so that the `ignored` entries stay where they are
+    fn track_unfolded(file: &Path, content: &Path, ignored: &[&Path]) -> Result<()> {
+        let mut dirs = vec![(content.to_path_buf(), file.symlink_metadata()?)];
+        fs::create_dir(content)?;
+        for entry in walk(file)? {
+            if ignored.iter().any(|path| entry.starts_with(path)) {
+                continue;
+            }
+            let source = file.join(&entry);
+            let target = content.join(&entry);
+            let meta = source.symlink_metadata()?;
+            if meta.is_dir() {
+                fs::create_dir(&target)?;
+                dirs.push((target, meta));
+            } else {
+                transfer::move_item(&source, &target)?;
+                unix::symlink(&target, &source)?;
+            }
+        }
+        // deepest first, so that directories without write access can still be filled
+        for (dir, meta) in dirs.into_iter().rev() {
+            fs::set_permissions(dir, meta.permissions())?;
+        }
+        Ok(())
     }
 
-    pub fn untrack(
+    pub fn adopt_orphans(
         &self,
         config: &Config,
-        file: &PathBuf,
-        confirm_delete: fn(&PathBuf) -> Result<()>,
+        confirm_adopt: fn(&PathBuf) -> Result<bool>,
     ) -> Result<Dotfiles> {
         let home = config.get_home()?;
-        if !file.starts_with(home.clone()) {
+        let contents = config.contents();
+
+        let mut adopted = vec![];
+        for orphan in self.get_orphaned_files(&contents)? {
+            if !confirm_adopt(&orphan)? {
+                warn!("Skipping orphan {:?}", orphan);
+                continue;
+            }
+            let symlink = Symlink::get(&contents, &home, &orphan);
+            if let SymlinkStatus::Wrong = symlink.status {
+                Err(anyhow!(
+                    "Cannot adopt {:?} because {:?} already exists",
+                    orphan,
+                    symlink.path
+                ))?
+            }
+            adopted.push((orphan, symlink));
+        }
+
+        let mut files = self.get_files();
+        let mut executables = self.get_executables();
+        for (orphan, symlink) in adopted {
+            info!("Adopting {:?}", orphan);
+            if let SymlinkStatus::Absent(_) = symlink.status {
+                self.create_parents(&home, &orphan)?;
+                symlink.create()?;
+            }
+            if symlink.expected.is_file() && Executable::get(&symlink.expected)? == Executable::Yes
+            {
+                executables.push(orphan.clone());
+            }
+            files.push(orphan);
+        }
+
+        Ok(self.updated(files, executables, self.get_deleted()))
+    }
+
+    fn check_track_link(
+        &self,
+        config: &Config,
+        file: &PathBuf,
+        validate_relative: fn(&Config, &Path) -> Result<()>,
+    ) -> Result<PathBuf> {
+        // canonicalize everything but the symlink itself
+        let name = result_from_option(
+            file.file_name(),
+            format!("Cannot track {:?} because it has no file name", file),
+        )?;
+        let file = match file.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.canonicalize()?,
+            _ => std::env::current_dir()?.canonicalize()?,
+        }
+        .join(name);
+
+        let resolved = file.canonicalize();
+        if resolved.is_ok_and(|resolved| resolved.starts_with(config.contents())) {
             Err(anyhow!(
-                "Cannot untrack {:?} because it is not in the home directory {:?}",
+                "Cannot track {:?} because it is managed by {}",
                 file,
-                home
+                APP_NAME
+            ))?
+        }
+        let home = config.get_home()?;
+        let relative = result_from_option(
+            paths::home_relative(&home, &file),
+            format!(
+                "Cannot track {:?} because it is not in the home directory {:?}",
+                file, home
+            ),
+        )?;
+        validate_relative(config, &relative)?;
+        if self.get_links().contains_key(&relative) {
+            Err(anyhow!(
+                "Cannot track {:?} because it is already tracked",
+                file
+            ))?
+        }
+        if let Some(other) = self
+            .get_files()
+            .iter()
+            .find(|f| paths::overlaps(f, &relative))
+        {
+            Err(anyhow!(
+                "Cannot track {:?} because it overlaps with the tracked {:?}",
+                relative,
+                other
             ))?
         }
 
-        let mut files = self.get_files();
-        let relative = paths::relative_to(&home, file);
-        if !files.contains(&relative) {
+        if self.get_deleted().contains(&relative) {
+            Err(anyhow!(
+                "Cannot track {:?} because it has been deleted",
+                file
+            ))?
+        }
+        Ok(relative)
+    }
+
+    pub fn check_untrack(&self, config: &Config, file: &PathBuf) -> Result<PathBuf> {
+        let home = config.get_home()?;
+        let relative = result_from_option(
+            paths::home_relative(&home, &std::path::absolute(file)?),
+            format!(
+                "Cannot untrack {:?} because it is not in the home directory {:?}",
+                file, home
+            ),
+        )?;
+        if self.get_links().contains_key(&relative) {
+            return Ok(relative);
+        }
+        if !self.get_files().contains(&relative) {
             Err(anyhow!(
                 "Cannot untrack {:?} because it is not tracked",
                 relative
             ))?
         }
-
-        let mut deleted = self.get_deleted();
-        if deleted.contains(&relative) {
+        if self.get_deleted().contains(&relative) {
             Err(anyhow!(
                 "Cannot untrack {:?} because it has already been deleted",
                 relative
             ))?
         }
+        Ok(relative)
+    }
 
-        let mut dest = config.contents();
-        dest.push(relative.clone());
+    pub fn untrack(
+        &self,
+        config: &Config,
+        file: &PathBuf,
+        confirm_delete: fn(&PathBuf) -> Result<()>,
+    ) -> Result<Dotfiles> {
+        let relative = self.check_untrack(config, file)?;
+
+        let mut files = self.get_files();
+        let mut links = self.get_links();
+        if links.remove(&relative).is_some() {
+            if file.symlink_metadata().is_ok() {
+                confirm_delete(file)?;
+                fs::remove_file(file)?;
+            }
+            return Ok(Dotfiles {
+                links,
+                ..self.canonicalize()
+            }
+            .pruned());
+        }
 
+        let mut deleted = self.get_deleted();
+        let dest = config.contents().join(&relative);
         confirm_delete(&dest)?;
-        fs::remove_file(dest)?;
 
-        confirm_delete(file)?;
-        fs::remove_file(file)?;
+        // the home directory is cleaned up first, so that the content survives a failure. The
+        // symlink may be missing already
+        if file.symlink_metadata().is_ok() {
+            confirm_delete(file)?;
+            if self.get_unfolded().contains(&relative) {
+                Self::remove_unfolded(&dest, file)?;
+            } else {
+                fs::remove_file(file)?;
+            }
+        }
+        transfer::delete_item(&dest)?;
 
         let mut executables = self.get_executables();
         remove_item(&mut executables, &relative);
@@ -417,36 +1556,615 @@ impl Dotfiles {
 
         deleted.push(relative);
 
-        Ok(Dotfiles::new(Some(files), Some(executables), Some(deleted)))
+        Ok(self.updated(files, executables, deleted))
     }
 
-    pub fn set_executable(
+    // removes the symlinks of the unfolded directory `dir` and then its directories, unless they
+    // contain anything else
+    fn remove_unfolded(content: &Path, dir: &Path) -> Result<()> {
+        fn remove_empty(dir: &Path) -> Result<()> {
+            match fs::remove_dir(dir) {
+                Err(err) if err.kind() == ErrorKind::DirectoryNotEmpty => {
+                    warn!("Keeping {:?}, which contains untracked files", dir);
+                    Ok(())
+                }
+                Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+                result => Ok(result?),
+            }
+        }
+
+        // children before their parents
+        for entry in walk(content)?.iter().rev() {
+            let (leaf, path) = (content.join(entry), dir.join(entry));
+            if leaf.symlink_metadata()?.is_dir() {
+                remove_empty(&path)?;
+            } else if path.read_link().is_ok_and(|target| target == leaf) {
+                fs::remove_file(&path)?;
+            }
+        }
+        remove_empty(dir)
+    }
+
+    pub fn mv(
         &self,
         config: &Config,
-        file: &PathBuf,
-        mode: Executable,
+        from: &PathBuf,
+        to: &PathBuf,
+        validate_relative: fn(&Config, &Path) -> Result<()>,
     ) -> Result<Dotfiles> {
         let home = config.get_home()?;
-        if !file.starts_with(home.clone()) {
+        let from_relative = result_from_option(
+            paths::home_relative(&home, from),
+            format!(
+                "Cannot move {:?} because it is not in the home directory {:?}",
+                from, home
+            ),
+        )?;
+        let to_relative = result_from_option(
+            paths::home_relative(&home, to),
+            format!(
+                "Cannot move {:?} because it is not in the home directory {:?}",
+                to, home
+            ),
+        )?;
+
+        let mut files = self.get_files();
+        let deleted = self.get_deleted();
+        validate_relative(config, &to_relative)?;
+        if !files.contains(&from_relative) {
             Err(anyhow!(
-                "Cannot modify executable flag of {:?} because it is not in the home directory {:?}",
+                "Cannot move {:?} because it is not tracked",
+                from_relative
+            ))?
+        }
+        if files.contains(&to_relative) {
+            Err(anyhow!(
+                "Cannot move to {:?} because it is already tracked",
+                to_relative
+            ))?
+        }
+        if deleted.contains(&to_relative) {
+            Err(anyhow!(
+                "Cannot move to {:?} because it has been deleted",
+                to_relative
+            ))?
+        }
+        if let Some(other) = files
+            .iter()
+            .find(|f| **f != from_relative && paths::overlaps(f, &to_relative))
+        {
+            Err(anyhow!(
+                "Cannot move to {:?} because it overlaps with the tracked {:?}",
+                to_relative,
+                other
+            ))?
+        }
+
+        if self.get_unfolded().contains(&from_relative) {
+            Err(anyhow!(
+                "Cannot move {:?} because it is unfolded, please fold it first",
+                from_relative
+            ))?
+        }
+
+        let contents = config.contents();
+        let old = Symlink::get(&contents, &home, &from_relative);
+        if let SymlinkStatus::Wrong = old.status {
+            Err(anyhow!(
+                "Cannot move {:?} because it is not a symlink to {:?}",
+                from,
+                old.expected
+            ))?
+        }
+        let new = Symlink::get(&contents, &home, &to_relative);
+        if !matches!(new.status, SymlinkStatus::Absent(_)) {
+            Err(anyhow!("Cannot move to {:?} because it already exists", to))?
+        }
+        if new.expected.symlink_metadata().is_ok() {
+            Err(anyhow!(
+                "Cannot move to {:?} because {:?} already exists",
+                to,
+                new.expected
+            ))?
+        }
+
+        info!("Moving {:?} to {:?}", from_relative, to_relative);
+        // The outermost content directory created for the move, removed again on failure
+        let created = new
+            .expected
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.symlink_metadata().is_err())
+            .last()
+            .map(Path::to_path_buf);
+        if let Some(parent) = new.expected.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&old.expected, &new.expected)?;
+        let linked = matches!(old.status, SymlinkStatus::Ok);
+        let relinked = (|| {
+            if linked {
+                fs::remove_file(&old.path)?;
+            }
+            self.create_parents(&home, &to_relative)?;
+            new.create()
+        })();
+        if let Err(err) = relinked {
+            // Put the content back so the manifest still matches the file system
+            warn!("Moving {:?} failed, restoring it", from_relative);
+            fs::rename(&new.expected, &old.expected)?;
+            if let Some(dir) = created {
+                fs::remove_dir_all(dir)?;
+            }
+            if linked && old.path.symlink_metadata().is_err() {
+                old.create()?;
+            }
+            return Err(err);
+        }
+
+        let mut executables = self.get_executables();
+        for list in [&mut files, &mut executables] {
+            if let Some(entry) = list.iter_mut().find(|entry| **entry == from_relative) {
+                *entry = to_relative.clone();
+            }
+        }
+        let mut owners = self.get_owners();
+        if let Some(owner) = owners.remove(&from_relative) {
+            owners.insert(to_relative, owner);
+        }
+
+        Ok(Dotfiles {
+            owners,
+            ..self.updated(files, executables, deleted)
+        })
+    }
+
+    pub fn merge(&self, config: &Config, file: &PathBuf) -> Result<Dotfiles> {
+        let home = config.get_home()?;
+        let relative = result_from_option(
+            paths::home_relative(&home, file),
+            format!(
+                "Cannot merge {:?} because it is not in the home directory {:?}",
                 file, home
+            ),
+        )?;
+
+        let mut files = self.get_files();
+        if !files.contains(&relative) {
+            Err(anyhow!(
+                "Cannot merge {:?} because it is not tracked",
+                relative
+            ))?
+        }
+        let parent = result_from_option(
+            files
+                .iter()
+                .find(|f| **f != relative && relative.starts_with(f))
+                .cloned(),
+            format!("Cannot merge {:?} because no parent is tracked", relative),
+        )?;
+
+        let content = config.contents().join(&relative);
+        if content.symlink_metadata()?.file_type().is_symlink() {
+            Err(anyhow!(
+                "Cannot merge {:?} because its content {:?} is a symlink, please resolve manually",
+                relative,
+                content
             ))?
         }
 
+        info!("Merging {:?} into {:?}", relative, parent);
+        let mut executables = self.get_executables();
+        remove_item(&mut executables, &relative);
+        remove_item(&mut files, &relative);
+
+        Ok(self.updated(files, executables, self.get_deleted()))
+    }
+
+    pub fn unfold(&self, config: &Config, dir: &PathBuf) -> Result<Dotfiles> {
+        let home = config.get_home()?;
+        let relative = result_from_option(
+            paths::home_relative(&home, dir),
+            format!(
+                "Cannot unfold {:?} because it is not in the home directory {:?}",
+                dir, home
+            ),
+        )?;
+
+        if !self.get_files().contains(&relative) {
+            Err(anyhow!(
+                "Cannot unfold {:?} because it is not tracked",
+                relative
+            ))?
+        }
+        let mut unfolded = self.get_unfolded();
+        if unfolded.contains(&relative) {
+            Err(anyhow!(
+                "Cannot unfold {:?} because it is already unfolded",
+                relative
+            ))?
+        }
+
+        let contents = config.contents();
+        let symlink = Symlink::get(&contents, &home, &relative);
+        if !symlink.expected.is_dir() {
+            Err(anyhow!(
+                "Cannot unfold {:?} because it is not a directory",
+                relative
+            ))?
+        }
+        if !matches!(symlink.status, SymlinkStatus::Ok) {
+            Err(anyhow!(
+                "Cannot unfold {:?} because it is not a symlink to {:?}",
+                relative,
+                symlink.expected
+            ))?
+        }
+
+        fn refuse_behaviour(path: &PathBuf) -> Result<RepairAction> {
+            Err(anyhow!("Unexpected file {:?}", path))
+        }
+
+        self.prepare_unfolded(&contents, &home, &relative, refuse_behaviour)?;
+        unfolded.push(relative);
+        let dotfiles = Dotfiles {
+            unfolded: Some(unfolded),
+            ..self.canonicalize()
+        };
+        for symlink in dotfiles.get_symlinks(&contents, &home)?.values() {
+            if let SymlinkStatus::Absent(_) = symlink.status {
+                symlink.create()?;
+            }
+        }
+        Ok(dotfiles)
+    }
+
+    // the manifest is saved after each unfolding, so that it matches the home directory even if
+    // moving fails midway
+    pub fn move_ignored(
+        &self,
+        config: &Config,
+        save: fn(&Config, &Dotfiles) -> Result<()>,
+    ) -> Result<Dotfiles> {
+        // moves `source` to `target`, replacing symlinks to it and merging directories
+        fn move_out(source: &Path, target: &Path) -> Result<()> {
+            if let Ok(meta) = target.symlink_metadata() {
+                if meta.file_type().is_symlink() && target.read_link()? == source {
+                    fs::remove_file(target)?;
+                } else if !(meta.is_dir() && source.symlink_metadata()?.is_dir()) {
+                    Err(anyhow!(
+                        "Cannot move {:?} out because {:?} already exists",
+                        source,
+                        target
+                    ))?
+                }
+            }
+            if target.symlink_metadata().is_err() {
+                return transfer::move_item(source, target);
+            }
+            for entry in fs::read_dir(source)? {
+                let name = entry?.file_name();
+                move_out(&source.join(&name), &target.join(&name))?;
+            }
+            fs::remove_dir(source)?;
+            Ok(())
+        }
+
+        let home = config.get_home()?;
+        let contents = config.contents();
+        let ignored = self.get_ignored_files(&contents)?;
+        let mut dotfiles = self.canonicalize();
+        for dotfile in self.get_files() {
+            if !ignored.iter().any(|path| path.starts_with(&dotfile)) {
+                continue;
+            }
+            if !dotfiles.get_unfolded().contains(&dotfile) {
+                dotfiles = dotfiles.unfold(config, &home.join(&dotfile))?;
+                save(config, &dotfiles)?;
+            }
+        }
+        for path in ignored {
+            info!("Moving ignored {:?} out of the content", path);
+            move_out(&contents.join(&path), &home.join(&path))?;
+        }
+        Ok(dotfiles)
+    }
+
+    // only on request, tracking several files in a directory keeps them as separate entries
+    pub fn fold(&self, config: &Config, dir: &PathBuf) -> Result<Dotfiles> {
+        let home = config.get_home()?;
+        let relative = result_from_option(
+            paths::home_relative(&home, dir),
+            format!(
+                "Cannot fold {:?} because it is not in the home directory {:?}",
+                dir, home
+            ),
+        )?;
+
         let files = self.get_files();
-        let relative = paths::relative_to(&home, file);
-        if !files.contains(&relative) {
+        let unfolded = self.get_unfolded();
+        if let Some(parent) = files
+            .iter()
+            .find(|f| **f != relative && relative.starts_with(f))
+        {
+            Err(anyhow!(
+                "Cannot fold {:?} because it is inside the tracked {:?}",
+                relative,
+                parent
+            ))?
+        }
+        if files.contains(&relative) && !unfolded.contains(&relative) {
+            Err(anyhow!(
+                "Cannot fold {:?} because it is already folded",
+                relative
+            ))?
+        }
+        let (entries, mut files): (Vec<PathBuf>, Vec<PathBuf>) =
+            files.into_iter().partition(|f| f.starts_with(&relative));
+        if entries.is_empty() {
+            Err(anyhow!(
+                "Cannot fold {:?} because nothing inside is tracked",
+                relative
+            ))?
+        }
+        // they would not apply to the folded directory in the same way
+        for entry in entries.iter().filter(|entry| **entry != relative) {
+            if self.owners.contains_key(entry) || self.entry_ignore.contains_key(entry) {
+                Err(anyhow!(
+                    "Cannot fold {:?} because {:?} has an owner or ignore patterns, remove them first",
+                    relative,
+                    entry
+                ))?
+            }
+        }
+
+        let contents = config.contents();
+        let expected = contents.join(&relative);
+        if !expected.is_dir() {
+            Err(anyhow!(
+                "Cannot fold {:?} because {:?} is not a directory",
+                relative,
+                expected
+            ))?
+        }
+        for entry in walk(&expected)? {
+            let is_dir = expected.join(&entry).symlink_metadata()?.is_dir();
+            let entry = relative.join(entry);
+            if !is_dir && !entries.iter().any(|f| entry.starts_with(f)) {
+                Err(anyhow!(
+                    "Cannot fold {:?} because its content {:?} is not tracked",
+                    relative,
+                    entry
+                ))?
+            }
+        }
+
+        let path = home.join(&relative);
+        if !path.symlink_metadata()?.is_dir() {
+            Err(anyhow!(
+                "Cannot fold {:?} because it is not a real directory",
+                path
+            ))?
+        }
+        for entry in walk(&path)? {
+            let meta = path.join(&entry).symlink_metadata()?;
+            let managed = meta.is_dir()
+                || (meta.file_type().is_symlink()
+                    && path.join(&entry).read_link()? == expected.join(&entry));
+            if !managed {
+                Err(anyhow!(
+                    "Cannot fold {:?} because {:?} is not managed",
+                    relative,
+                    path.join(entry)
+                ))?
+            }
+        }
+
+        info!("Folding {:?}", relative);
+        fs::remove_dir_all(&path)?;
+        Symlink::get(&contents, &home, &relative).create()?;
+
+        let mut executables = self.get_executables();
+        let mut unfolded = unfolded;
+        for entry in &entries {
+            if executables.contains(entry) {
+                warn!("Executable flag of {:?} is no longer tracked", entry);
+            }
+            remove_item(&mut executables, entry);
+            remove_item(&mut unfolded, entry);
+        }
+        files.push(relative);
+
+        Ok(Dotfiles {
+            unfolded: Some(unfolded).filter(|unfolded| !unfolded.is_empty()),
+            ..self.updated(files, executables, self.get_deleted())
+        })
+    }
+
+    pub fn declare_dir(
+        &self,
+        config: &Config,
+        dir: &PathBuf,
+        mode: Option<Perms>,
+    ) -> Result<Dotfiles> {
+        let home = config.get_home()?;
+        let relative = result_from_option(
+            paths::home_relative(&home, dir),
+            format!(
+                "Cannot declare {:?} because it is not in the home directory {:?}",
+                dir, home
+            ),
+        )?;
+
+        if let Some(f) = self.get_files().iter().find(|f| relative.starts_with(f)) {
+            Err(anyhow!(
+                "Cannot declare {:?} because it overlaps with the tracked {:?}",
+                relative,
+                f
+            ))?
+        }
+        let mode = match mode {
+            Some(mode) => mode,
+            None => match dir.symlink_metadata() {
+                Ok(meta) if meta.is_dir() => Perms::from_mode(meta.permissions().mode()),
+                _ => Err(anyhow!(
+                    "Cannot declare {:?} without a mode because it is not a directory",
+                    relative
+                ))?,
+            },
+        };
+
+        let directory = Directory::get(&home, &relative, mode)?;
+        match directory.status {
+            DirectoryStatus::Wrong => Err(anyhow!(
+                "Cannot declare {:?} because it is not a directory",
+                relative
+            ))?,
+            DirectoryStatus::Symlink(ref target) => Err(anyhow!(
+                "Cannot declare {:?} because it is a symlink to {:?}",
+                relative,
+                target
+            ))?,
+            DirectoryStatus::Absent => self.create_parents(&home, &relative)?,
+            _ => {}
+        }
+        directory.repair(|_| Err(anyhow!("Unexpected file")))?;
+
+        let mut dirs = self.get_dirs();
+        dirs.insert(relative, mode);
+        Ok(Dotfiles {
+            dirs,
+            ..self.canonicalize()
+        })
+    }
+
+    pub fn undeclare_dir(&self, config: &Config, dir: &Path) -> Result<Dotfiles> {
+        let home = config.get_home()?;
+        let relative = result_from_option(
+            paths::home_relative(&home, dir),
+            format!(
+                "Cannot undeclare {:?} because it is not in the home directory {:?}",
+                dir, home
+            ),
+        )?;
+        let mut dirs = self.get_dirs();
+        if dirs.remove(&relative).is_none() {
+            Err(anyhow!(
+                "Cannot undeclare {:?} because it is not declared",
+                relative
+            ))?
+        }
+        Ok(Dotfiles {
+            dirs,
+            ..self.canonicalize()
+        }
+        .pruned())
+    }
+
+    pub fn check_set_owner(
+        &self,
+        config: &Config,
+        file: &PathBuf,
+        owner: Option<Owner>,
+    ) -> Result<PathBuf> {
+        let home = config.get_home()?;
+        let relative = result_from_option(
+            paths::home_relative(&home, file),
+            format!(
+                "Cannot modify owner of {:?} because it is not in the home directory {:?}",
+                file, home
+            ),
+        )?;
+        if !self.get_files().contains(&relative) && !self.dirs.contains_key(&relative) {
+            Err(anyhow!(
+                "Cannot modify owner of {:?} because it is neither tracked nor a declared directory",
+                relative
+            ))?
+        }
+        // the content of the system scope has to stay owned by root, see `Layers::load`
+        if let Some(uid) = owner.and_then(|owner| owner.uid) {
+            if config.is_system_scope() && uid != 0 {
+                Err(anyhow!(
+                    "Cannot record uid {} for {:?} because system files have to be owned by root",
+                    uid,
+                    relative
+                ))?
+            }
+        }
+        Ok(relative)
+    }
+
+    // `None` forgets the owner
+    pub fn set_owner(
+        &self,
+        config: &Config,
+        file: &PathBuf,
+        owner: Option<Owner>,
+    ) -> Result<Dotfiles> {
+        let relative = self.check_set_owner(config, file, owner)?;
+        let mut owners = self.get_owners();
+        owners.remove(&relative);
+        if let Some(owner) = owner {
+            let single = Dotfiles {
+                owners: BTreeMap::from([(relative.clone(), owner)]),
+                ..self.canonicalize()
+            };
+            for (path, owner, _) in single.get_owner_mismatches(config)? {
+                info!("Changing owner of {:?} to {}", path, owner);
+                owner.set(&path).map_err(|err| {
+                    anyhow!("Cannot change owner of {:?} to {}: {}", path, owner, err)
+                })?;
+            }
+            owners.insert(relative, owner);
+        }
+        Ok(Dotfiles {
+            owners,
+            ..self.canonicalize()
+        })
+    }
+
+    pub fn check_set_executable(
+        &self,
+        config: &Config,
+        file: &PathBuf,
+        mode: Executable,
+    ) -> Result<PathBuf> {
+        let home = config.get_home()?;
+        let relative = result_from_option(
+            paths::home_relative(&home, file),
+            format!(
+                "Cannot modify executable flag of {:?} because it is not in the home directory {:?}",
+                file, home
+            ),
+        )?;
+        if !self.get_files().contains(&relative) {
             Err(anyhow!(
                 "Cannot modify executable flag of {:?} because it is not tracked",
                 relative
             ))?
         }
+        if mode == Executable::Yes && config.contents().join(&relative).is_dir() {
+            Err(anyhow!(
+                "Cannot set executable flag of {:?} because it is a directory",
+                relative
+            ))?
+        }
+        Ok(relative)
+    }
+
+    pub fn set_executable(
+        &self,
+        config: &Config,
+        file: &PathBuf,
+        mode: Executable,
+    ) -> Result<Dotfiles> {
+        let relative = self.check_set_executable(config, file, mode)?;
+        let home = config.get_home()?;
 
         let mut executables = self.get_executables();
         let contained = executables.contains(&relative);
 
-        Symlink::get(&config.contents(), &home, file).set_executable(mode)?;
+        Symlink::get(&config.contents(), &home, &relative).set_executable(mode)?;
 
         match mode {
             Executable::No => remove_item(&mut executables, &relative),
@@ -457,11 +2175,7 @@ impl Dotfiles {
             }
         }
 
-        Ok(Dotfiles::new(
-            Some(files),
-            Some(executables),
-            Some(self.get_deleted()),
-        ))
+        Ok(self.updated(self.get_files(), executables, self.get_deleted()))
     }
 
     pub fn repair(
@@ -472,20 +2186,78 @@ impl Dotfiles {
         let home = config.get_home()?;
         info!("Attempting to repair {:?}", home);
 
-        let symlinks = self.get_symlinks(config.contents().as_path(), home.as_path());
+        let contents = config.contents();
+        let mut results = vec![];
+        let mut skipped = vec![];
+        for (dir, directory) in self.get_directories(&home)? {
+            if let DirectoryStatus::Absent = directory.status {
+                self.create_parents(&home, &dir)?;
+            }
+            let result = directory.repair(wrong_behaviour)?;
+            if result == RepairResult::Skipped {
+                skipped.push(dir);
+            }
+            results.push(result);
+        }
+        for dotfile in self.get_unfolded() {
+            let result = self.prepare_unfolded(&contents, &home, &dotfile, wrong_behaviour)?;
+            if result == RepairResult::Skipped {
+                skipped.push(dotfile);
+            }
+            results.push(result);
+        }
+
+        let symlinks = self.get_symlinks(contents.as_path(), home.as_path())?;
+        let files = self.get_files();
         let executables = self.get_executables();
 
-        let skippeds: Result<_> = symlinks
-            .iter()
-            .map(|(dotfile, symlink)| {
-                symlink.repair(
-                    wrong_behaviour,
-                    Executable::from(executables.contains(dotfile)),
-                )
-            })
-            .collect();
+        for (dotfile, symlink) in &symlinks {
+            if skipped.iter().any(|dir| dotfile.starts_with(dir)) {
+                continue;
+            }
+            if let SymlinkStatus::Absent(_) = symlink.status {
+                self.create_parents(&home, dotfile)?;
+            }
+            let mode = if files.contains(dotfile) {
+                Some(Executable::from(executables.contains(dotfile)))
+            } else {
+                None
+            };
+            results.push(symlink.repair(wrong_behaviour, mode)?);
+        }
+
+        for (link, symlink) in &self.get_link_symlinks(&home) {
+            if skipped.iter().any(|dir| link.starts_with(dir)) {
+                continue;
+            }
+            if let SymlinkStatus::Absent(_) = symlink.status {
+                self.create_parents(&home, link)?;
+            }
+            results.push(symlink.repair(wrong_behaviour, None)?);
+        }
+
+        for (path, owner, _) in self.get_owner_mismatches(config)? {
+            info!("Changing owner of {:?} to {}", path, owner);
+            results.push(owner_repair_result(&path, &owner, owner.set(&path))?);
+        }
+
+        Ok(RepairResult::coalesce_all(results))
+    }
+}
 
-        Ok(RepairResult::coalesce_all(skippeds?))
+// changing an owner usually requires root privileges, so lacking them only skips the repair
+fn owner_repair_result(
+    path: &Path,
+    owner: &Owner,
+    result: std::io::Result<()>,
+) -> Result<RepairResult> {
+    match result {
+        Ok(()) => Ok(RepairResult::Successful),
+        Err(err) if err.kind() == ErrorKind::PermissionDenied => {
+            warn!("Cannot change owner of {:?} to {}: {}", path, owner, err);
+            Ok(RepairResult::Skipped)
+        }
+        Err(err) => Err(err)?,
     }
 }
 
@@ -494,8 +2266,17 @@ mod tests {
     use crate::config::test_util::*;
     use crate::config::Config;
     use crate::dotfiles::*;
-    use std::fs::File;
+    use crate::perm::{is_root, MODE_MASK};
+    use std::fs::{File, Permissions};
+    use std::io::Write;
     use std::os::unix::fs as unix;
+    use std::os::unix::fs::PermissionsExt;
+
+    // checks the manifest, the content and the home directory, like the check command
+    fn check(dotfiles: &Dotfiles, config: &Config) -> Result<()> {
+        dotfiles.check_contents(config)?;
+        dotfiles.check_home(config)
+    }
 
     #[test]
     fn test_empty_dotfiles() {
@@ -550,7 +2331,7 @@ mod tests {
             None,
             Some(vec![]),
         );
-        dotfiles.check(&config).unwrap();
+        check(&dotfiles, &config).unwrap();
     }
 
     #[test]
@@ -558,7 +2339,7 @@ mod tests {
     fn test_check_failure_missing() {
         let (_dir, config) = setup_config();
         let dotfiles = Dotfiles::new(Some(vec![PathBuf::from(".test")]), None, Some(vec![]));
-        dotfiles.check(&config).unwrap();
+        check(&dotfiles, &config).unwrap();
     }
 
     #[test]
@@ -568,7 +2349,7 @@ mod tests {
         let file = ".test";
         setup_content(&config, file);
         let dotfiles = Dotfiles::new(Some(vec![PathBuf::from(file)]), None, Some(vec![]));
-        dotfiles.check(&config).unwrap();
+        check(&dotfiles, &config).unwrap();
     }
 
     #[test]
@@ -583,7 +2364,172 @@ mod tests {
                 .repair(&config, |_| Ok(RepairAction::Skip))
                 .unwrap()
         );
-        dotfiles.check(&config).unwrap();
+        check(&dotfiles, &config).unwrap();
+    }
+
+    #[test]
+    fn test_repair_parents() {
+        let (_dir, config) = setup_config();
+        let file = ".ssh/config/app";
+        fs::create_dir_all(config.contents().join(".ssh/config")).unwrap();
+        setup_content(&config, file);
+        let dotfiles = Dotfiles {
+            parent_modes: BTreeMap::from([(PathBuf::from(".ssh"), "0700".parse().unwrap())]),
+            ..Dotfiles::new(Some(vec![PathBuf::from(file)]), None, Some(vec![]))
+        };
+        assert_eq!(
+            RepairResult::Successful,
+            dotfiles
+                .repair(&config, |_| Ok(RepairAction::Skip))
+                .unwrap()
+        );
+        check(&dotfiles, &config).unwrap();
+        dotfiles.save(&config).unwrap();
+        assert_eq!(Dotfiles::load(&config).unwrap(), dotfiles.canonicalize());
+
+        let home = config.get_home().unwrap();
+        let mode = |dir: &str| {
+            Perms::try_from(fs::metadata(home.join(dir)).unwrap().permissions()).unwrap()
+        };
+        assert_eq!(mode(".ssh"), Perms::UR | Perms::UW | Perms::UX);
+        assert!(mode(".ssh/config").contains(Perms::UR | Perms::UW | Perms::UX));
+    }
+
+    #[test]
+    fn test_dirs() {
+        let (temp, config) = setup_config();
+        let home = config.get_home().unwrap();
+        let dir = home.join(".cache/app");
+        let dotfiles = Dotfiles::new(Some(vec![]), None, Some(vec![]));
+        let dotfiles = dotfiles
+            .declare_dir(&config, &dir, Some("0750".parse().unwrap()))
+            .unwrap();
+        check(&dotfiles, &config).unwrap();
+
+        fs::set_permissions(&dir, Permissions::from_mode(0o755)).unwrap();
+        assert!(check(&dotfiles, &config).is_err());
+        dotfiles
+            .repair(&config, |_| Ok(RepairAction::Skip))
+            .unwrap();
+        check(&dotfiles, &config).unwrap();
+
+        assert!(dotfiles.undeclare_dir(&config, Path::new("/tmp")).is_err());
+        // the same directory through a different path to the home directory
+        let alias = temp.path().join("alias");
+        unix::symlink(&home, &alias).unwrap();
+        let dotfiles = dotfiles
+            .undeclare_dir(&config, &alias.join(".cache/app"))
+            .unwrap();
+        assert!(dotfiles.get_dirs().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "is not a directory, expected directory")]
+    fn test_dirs_wrong() {
+        let (_dir, config) = setup_config();
+        setup_dotfile(&config, ".ssh");
+        let dotfiles = Dotfiles {
+            dirs: BTreeMap::from([(PathBuf::from(".ssh"), "0700".parse().unwrap())]),
+            ..Dotfiles::new(Some(vec![]), None, Some(vec![]))
+        };
+        check(&dotfiles, &config).unwrap();
+    }
+
+    #[test]
+    fn test_dirs_special_bits() {
+        let (_dir, config) = setup_config();
+        let home = config.get_home().unwrap();
+        let dir = home.join(".shared");
+        fs::create_dir(&dir).unwrap();
+        fs::set_permissions(&dir, Permissions::from_mode(0o3770)).unwrap();
+        let dotfiles = Dotfiles::new(Some(vec![]), None, Some(vec![]));
+        let dotfiles = dotfiles.declare_dir(&config, &dir, None).unwrap();
+        assert_eq!(
+            dotfiles.get_dirs(),
+            BTreeMap::from([(PathBuf::from(".shared"), Perms::from_mode(0o770))])
+        );
+        check(&dotfiles, &config).unwrap();
+
+        fs::set_permissions(&dir, Permissions::from_mode(0o3700)).unwrap();
+        dotfiles
+            .repair(&config, |_| Ok(RepairAction::Skip))
+            .unwrap();
+        check(&dotfiles, &config).unwrap();
+        let mode = fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & MODE_MASK, 0o3770);
+    }
+
+    #[test]
+    fn test_dirs_symlink() {
+        let (dir, config) = setup_config();
+        let home = config.get_home().unwrap();
+        let elsewhere = dir.path().join("elsewhere");
+        fs::create_dir(&elsewhere).unwrap();
+        fs::write(elsewhere.join("data"), "").unwrap();
+        unix::symlink(&elsewhere, home.join(".cache")).unwrap();
+        let dotfiles = Dotfiles {
+            dirs: BTreeMap::from([(PathBuf::from(".cache"), "0700".parse().unwrap())]),
+            ..Dotfiles::new(Some(vec![]), None, Some(vec![]))
+        };
+        let err = check(&dotfiles, &config).unwrap_err();
+        assert!(err.to_string().contains("is a symlink to"));
+        assert!(dotfiles.get_conflicts(&config).unwrap().is_empty());
+
+        // even a forced repair keeps the symlink and its data
+        dotfiles
+            .repair(&config, |_| Ok(RepairAction::Delete))
+            .unwrap();
+        assert!(home.join(".cache").read_link().is_ok());
+        assert!(elsewhere.join("data").exists());
+    }
+
+    #[test]
+    #[should_panic(expected = "has been deleted")]
+    fn test_track_link_deleted() {
+        let (_dir, config) = setup_config();
+        let home = config.get_home().unwrap();
+        let link = home.join(".xsessionrc");
+        unix::symlink(".xprofile", &link).unwrap();
+        let dotfiles = Dotfiles::new(Some(vec![]), None, Some(vec![PathBuf::from(".xsessionrc")]));
+        dotfiles.track(&config, &link, |_, _| Ok(())).unwrap();
+    }
+
+    #[test]
+    fn test_track_link() {
+        let (_dir, config) = setup_config();
+        let home = config.get_home().unwrap();
+        let link = home.join(".xsessionrc");
+        unix::symlink(".xprofile", &link).unwrap();
+        let dotfiles = Dotfiles::load(&config).unwrap();
+        let dotfiles = dotfiles.track(&config, &link, |_, _| Ok(())).unwrap();
+        assert_eq!(
+            dotfiles.get_links(),
+            BTreeMap::from([(PathBuf::from(".xsessionrc"), PathBuf::from(".xprofile"))])
+        );
+        check(&dotfiles, &config).unwrap();
+
+        fs::remove_file(&link).unwrap();
+        unix::symlink(".profile", &link).unwrap();
+        assert!(check(&dotfiles, &config).is_err());
+        dotfiles
+            .repair(&config, |_| Ok(RepairAction::Delete))
+            .unwrap();
+        check(&dotfiles, &config).unwrap();
+        assert_eq!(link.read_link().unwrap(), PathBuf::from(".xprofile"));
+
+        let dotfiles = dotfiles.untrack(&config, &link, |_| Ok(())).unwrap();
+        assert!(dotfiles.get_links().is_empty());
+        assert!(link.symlink_metadata().is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "managed by")]
+    fn test_track_managed_link() {
+        let (_dir, config) = setup_config();
+        let path = setup_dotfile(&config, ".test");
+        let dotfiles = Dotfiles::load(&config).unwrap();
+        let dotfiles = dotfiles.track(&config, &path, |_, _| Ok(())).unwrap();
+        dotfiles.track(&config, &path, |_, _| Ok(())).unwrap();
     }
 
     #[test]
@@ -599,7 +2545,7 @@ mod tests {
                 .repair(&config, |_| Ok(RepairAction::Delete))
                 .unwrap()
         );
-        dotfiles.check(&config).unwrap();
+        check(&dotfiles, &config).unwrap();
     }
 
     #[test]
@@ -616,7 +2562,47 @@ mod tests {
                 .repair(&config, |_| Ok(RepairAction::Skip))
                 .unwrap()
         );
-        dotfiles.check(&config).unwrap();
+        check(&dotfiles, &config).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "Orphaned content")]
+    fn test_check_failure_orphan() {
+        let (_dir, config) = setup_config();
+        setup_content(&config, ".test");
+        let dotfiles = Dotfiles::new(Some(vec![]), None, Some(vec![]));
+        check(&dotfiles, &config).unwrap();
+    }
+
+    #[test]
+    fn test_orphans_nested() {
+        let (_dir, config) = setup_config();
+        fs::create_dir_all(config.contents().join(".config/app")).unwrap();
+        setup_content(&config, ".config/app/tracked");
+        setup_content(&config, ".config/app/orphan");
+        setup_content(&config, ".config/other");
+        let dotfiles = Dotfiles::new(
+            Some(vec![PathBuf::from(".config/app/tracked")]),
+            None,
+            Some(vec![]),
+        );
+        assert_eq!(
+            dotfiles.get_orphaned_files(&config.contents()).unwrap(),
+            vec![
+                PathBuf::from(".config/app/orphan"),
+                PathBuf::from(".config/other")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_adopt_orphans() {
+        let (_dir, config) = setup_config();
+        setup_content(&config, ".test");
+        let dotfiles = Dotfiles::new(Some(vec![]), None, Some(vec![]));
+        let dotfiles = dotfiles.adopt_orphans(&config, |_| Ok(true)).unwrap();
+        assert_eq!(dotfiles.get_files(), vec![PathBuf::from(".test")]);
+        check(&dotfiles, &config).unwrap();
     }
 
     #[test]
@@ -625,8 +2611,8 @@ mod tests {
         let file = ".test";
         let path = setup_dotfile(&config, file);
         let dotfiles = Dotfiles::load(&config).unwrap();
-        let dotfiles = dotfiles.track(&config, &path, |_| Ok(())).unwrap();
-        dotfiles.check(&config).unwrap();
+        let dotfiles = dotfiles.track(&config, &path, |_, _| Ok(())).unwrap();
+        check(&dotfiles, &config).unwrap();
 
         let mut contents = String::new();
         File::open(config.contents().join(file))
@@ -635,4 +2621,565 @@ mod tests {
             .unwrap();
         assert_eq!(contents, file);
     }
+
+    #[test]
+    #[should_panic(expected = "inside a tracked directory")]
+    fn test_track_inside_tracked() {
+        let (_dir, config) = setup_config();
+        let home = config.get_home().unwrap();
+        fs::create_dir(home.join(".dir")).unwrap();
+        setup_dotfile(&config, ".dir/file");
+        let dotfiles = Dotfiles::load(&config).unwrap();
+        let dotfiles = dotfiles
+            .track(&config, &home.join(".dir"), |_, _| Ok(()))
+            .unwrap();
+        dotfiles
+            .track(&config, &home.join(".dir/file"), |_, _| Ok(()))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_merge() {
+        let (_dir, config) = setup_config();
+        let home = config.get_home().unwrap();
+        fs::create_dir(config.contents().join(".dir")).unwrap();
+        setup_content(&config, ".dir/file");
+        setup_symlink(&config, ".dir");
+        let dotfiles = Dotfiles::new(
+            Some(vec![PathBuf::from(".dir"), PathBuf::from(".dir/file")]),
+            None,
+            Some(vec![]),
+        );
+        assert!(check(&dotfiles, &config).is_err());
+        let dotfiles = dotfiles.merge(&config, &home.join(".dir/file")).unwrap();
+        assert_eq!(dotfiles.get_files(), vec![PathBuf::from(".dir")]);
+        check(&dotfiles, &config).unwrap();
+    }
+
+    fn setup_tree(config: &Config) {
+        fs::create_dir_all(config.contents().join(".dir/sub")).unwrap();
+        setup_content(config, ".dir/file");
+        setup_content(config, ".dir/sub/file");
+    }
+
+    #[test]
+    fn test_unfold() {
+        let (_dir, config) = setup_config();
+        let home = config.get_home().unwrap();
+        setup_tree(&config);
+        setup_symlink(&config, ".dir");
+        let dotfiles = Dotfiles::new(Some(vec![PathBuf::from(".dir")]), None, Some(vec![]));
+        let dotfiles = dotfiles.unfold(&config, &home.join(".dir")).unwrap();
+        check(&dotfiles, &config).unwrap();
+        assert!(home.join(".dir").symlink_metadata().unwrap().is_dir());
+        assert_eq!(
+            home.join(".dir/sub/file").read_link().unwrap(),
+            config.contents().join(".dir/sub/file")
+        );
+
+        let dotfiles = dotfiles.fold(&config, &home.join(".dir")).unwrap();
+        check(&dotfiles, &config).unwrap();
+        assert_eq!(dotfiles.get_unfolded(), Vec::<PathBuf>::new());
+    }
+
+    #[test]
+    fn test_repair_unfolded() {
+        let (_dir, config) = setup_config();
+        setup_tree(&config);
+        setup_symlink(&config, ".dir");
+        let dotfiles = Dotfiles {
+            unfolded: Some(vec![PathBuf::from(".dir")]),
+            ..Dotfiles::new(Some(vec![PathBuf::from(".dir")]), None, Some(vec![]))
+        };
+        assert!(check(&dotfiles, &config).is_err());
+        assert_eq!(
+            RepairResult::Successful,
+            dotfiles
+                .repair(&config, |_| Ok(RepairAction::Skip))
+                .unwrap()
+        );
+        check(&dotfiles, &config).unwrap();
+    }
+
+    #[test]
+    fn test_fold_children() {
+        let (_dir, config) = setup_config();
+        let home = config.get_home().unwrap();
+        setup_tree(&config);
+        fs::create_dir_all(home.join(".dir")).unwrap();
+        setup_symlink(&config, ".dir/file");
+        setup_symlink(&config, ".dir/sub");
+        let dotfiles = Dotfiles::new(
+            Some(vec![PathBuf::from(".dir/file"), PathBuf::from(".dir/sub")]),
+            None,
+            Some(vec![]),
+        );
+        check(&dotfiles, &config).unwrap();
+        // the owner of a child would be lost
+        let owned = Dotfiles {
+            owners: BTreeMap::from([(PathBuf::from(".dir/sub"), Owner::default())]),
+            ..dotfiles.canonicalize()
+        };
+        assert!(owned.fold(&config, &home.join(".dir")).is_err());
+        let dotfiles = dotfiles.fold(&config, &home.join(".dir")).unwrap();
+        check(&dotfiles, &config).unwrap();
+        assert_eq!(dotfiles.get_files(), vec![PathBuf::from(".dir")]);
+    }
+
+    #[test]
+    fn test_untrack_pruned() {
+        let (_dir, config) = setup_config();
+        let home = config.get_home().unwrap();
+        fs::create_dir_all(home.join(".emacs.d")).unwrap();
+        setup_dotfile(&config, ".emacs.d/init.el");
+        let dir = home.join(".emacs.d");
+        let dotfiles = Dotfiles::new(Some(vec![]), None, Some(vec![]))
+            .track(&config, &dir, |_, _| Ok(()))
+            .unwrap();
+        let dotfiles = Dotfiles {
+            entry_ignore: BTreeMap::from([(PathBuf::from(".emacs.d"), vec!["*.elc".to_string()])]),
+            overrides: vec![PathBuf::from(".emacs.d")],
+            ..dotfiles
+        };
+        check(&dotfiles, &config).unwrap();
+
+        let dotfiles = dotfiles.untrack(&config, &dir, |_| Ok(())).unwrap();
+        check(&dotfiles, &config).unwrap();
+        assert!(dotfiles.get_entry_ignore().is_empty());
+        assert!(dotfiles.get_overrides().is_empty());
+    }
+
+    #[test]
+    fn test_mv() {
+        let (_dir, config) = setup_config();
+        let home = config.get_home().unwrap();
+        let path = setup_dotfile(&config, ".test");
+        let dotfiles = Dotfiles::load(&config).unwrap();
+        let dotfiles = dotfiles.track(&config, &path, |_, _| Ok(())).unwrap();
+        let dotfiles = dotfiles
+            .set_executable(&config, &path, Executable::Yes)
+            .unwrap();
+        let target = home.join(".config/test/moved");
+        let dotfiles = dotfiles.mv(&config, &path, &target, |_, _| Ok(())).unwrap();
+        check(&dotfiles, &config).unwrap();
+
+        assert!(path.symlink_metadata().is_err());
+        assert_eq!(
+            dotfiles.get_files(),
+            vec![PathBuf::from(".config/test/moved")]
+        );
+        assert_eq!(dotfiles.get_executables(), dotfiles.get_files());
+        assert_eq!(dotfiles.get_deleted(), Vec::<PathBuf>::new());
+    }
+
+    #[test]
+    fn test_mv_rollback() {
+        let (_dir, config) = setup_config();
+        let home = config.get_home().unwrap();
+        let path = setup_dotfile(&config, ".test");
+        let dotfiles = Dotfiles::load(&config).unwrap();
+        let dotfiles = dotfiles.track(&config, &path, |_, _| Ok(())).unwrap();
+        // The symlink cannot be created below a regular file
+        fs::write(home.join(".blocker"), "").unwrap();
+        let target = home.join(".blocker/moved");
+        assert!(dotfiles.mv(&config, &path, &target, |_, _| Ok(())).is_err());
+        check(&dotfiles, &config).unwrap();
+        assert!(config.contents().join(".test").is_file());
+        assert!(!config.contents().join(".blocker/moved").exists());
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let (_dir, config) = setup_config();
+        let home = config.get_home().unwrap();
+        for file in [".correct", ".conflict", ".absent"] {
+            setup_content(&config, file);
+        }
+        setup_symlink(&config, ".correct");
+        setup_symlink_wrong(&config, ".conflict");
+        fs::create_dir(home.join(".dir")).unwrap();
+        let dotfiles = Dotfiles {
+            dirs: BTreeMap::from([(PathBuf::from(".dir"), Perms::from_bits_truncate(0o700))]),
+            ..Dotfiles::new(
+                Some(vec![
+                    PathBuf::from(".correct"),
+                    PathBuf::from(".conflict"),
+                    PathBuf::from(".absent"),
+                ]),
+                None,
+                Some(vec![]),
+            )
+        };
+        assert_eq!(
+            dotfiles.get_conflicts(&config).unwrap(),
+            vec![home.join(".conflict")]
+        );
+    }
+
+    #[test]
+    fn test_states() {
+        let (_dir, config) = setup_config();
+        let home = config.get_home().unwrap();
+        for file in [".correct", ".conflict", ".absent"] {
+            setup_content(&config, file);
+        }
+        setup_symlink(&config, ".correct");
+        setup_symlink_wrong(&config, ".conflict");
+        fs::create_dir(home.join(".dir")).unwrap();
+        fs::set_permissions(home.join(".dir"), Permissions::from_mode(0o755)).unwrap();
+        let dotfiles = Dotfiles {
+            dirs: BTreeMap::from([(PathBuf::from(".dir"), Perms::from_bits_truncate(0o700))]),
+            ..Dotfiles::new(
+                Some(vec![
+                    PathBuf::from(".correct"),
+                    PathBuf::from(".conflict"),
+                    PathBuf::from(".absent"),
+                ]),
+                None,
+                Some(vec![]),
+            )
+        };
+        assert_eq!(
+            dotfiles.get_states(&config).unwrap(),
+            BTreeMap::from([
+                (PathBuf::from(".absent"), "missing"),
+                (PathBuf::from(".conflict"), "wrong"),
+                (PathBuf::from(".correct"), "ok"),
+                (PathBuf::from(".dir"), "mode"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_eject() {
+        let (_dir, config) = setup_config();
+        let home = config.get_home().unwrap();
+        setup_content(&config, ".file");
+        fs::set_permissions(
+            config.contents().join(".file"),
+            Permissions::from_mode(0o750),
+        )
+        .unwrap();
+        setup_symlink(&config, ".file");
+        fs::create_dir(config.contents().join(".dir")).unwrap();
+        setup_content(&config, ".dir/file");
+        setup_symlink(&config, ".dir");
+        // an interrupted eject left an incomplete copy behind
+        fs::create_dir(home.join(".dir.dotfilesctl-eject")).unwrap();
+        let dotfiles = Dotfiles::new(
+            Some(vec![PathBuf::from(".file"), PathBuf::from(".dir")]),
+            None,
+            Some(vec![]),
+        );
+
+        dotfiles.eject(&config).unwrap();
+        let meta = home.join(".file").symlink_metadata().unwrap();
+        assert!(meta.is_file());
+        assert_eq!(meta.permissions().mode() & 0o777, 0o750);
+        assert!(home.join(".dir/file").symlink_metadata().unwrap().is_file());
+        assert!(home
+            .join(".dir.dotfilesctl-eject")
+            .symlink_metadata()
+            .is_err());
+        assert!(config.contents().join(".dir/file").is_file());
+
+        // ejecting again leaves the copies alone
+        dotfiles.eject(&config).unwrap();
+    }
+
+    #[test]
+    fn test_repoint() {
+        let (dir, config) = setup_config();
+        let home = config.get_home().unwrap();
+        setup_content(&config, ".file");
+        let old = dir.path().join("old");
+        unix::symlink(old.join("contents/.file"), home.join(".file")).unwrap();
+        let dotfiles = Dotfiles::new(Some(vec![PathBuf::from(".file")]), None, Some(vec![]));
+        assert!(check(&dotfiles, &config).is_err());
+        dotfiles.repoint(&config, &old, &config.target()).unwrap();
+        check(&dotfiles, &config).unwrap();
+    }
+
+    #[test]
+    fn test_track_symlinked_home() {
+        let (dir, config) = setup_config();
+        let home = config.get_home().unwrap();
+        let alias = dir.path().join("alias");
+        unix::symlink(&home, &alias).unwrap();
+        setup_dotfile(&config, ".vimrc");
+        let dotfiles = Dotfiles::new(Some(vec![]), None, Some(vec![]));
+
+        // the same file through a different path to the home directory
+        let dotfiles = dotfiles
+            .track(&config, &alias.join(".vimrc"), |_, _| Ok(()))
+            .unwrap();
+        assert_eq!(dotfiles.get_files(), vec![PathBuf::from(".vimrc")]);
+        check(&dotfiles, &config).unwrap();
+        let dotfiles = dotfiles
+            .set_executable(&config, &alias.join(".vimrc"), Executable::Yes)
+            .unwrap();
+        assert_eq!(dotfiles.get_executables(), vec![PathBuf::from(".vimrc")]);
+        let dotfiles = dotfiles
+            .untrack(&config, &alias.join(".vimrc"), |_| Ok(()))
+            .unwrap();
+        assert_eq!(dotfiles.get_deleted(), vec![PathBuf::from(".vimrc")]);
+    }
+
+    #[test]
+    fn test_check_track() {
+        let (_dir, config) = setup_config();
+        let home = config.get_home().unwrap();
+        setup_dotfile(&config, ".vimrc");
+        let dotfiles = Dotfiles::new(Some(vec![]), None, Some(vec![]));
+
+        let relative = dotfiles
+            .check_track(&config, &home.join(".vimrc"), |_, _| Ok(()))
+            .unwrap();
+        assert_eq!(relative, PathBuf::from(".vimrc"));
+        // checking does not modify anything
+        assert!(home.join(".vimrc").is_file());
+        assert!(!home.join(".vimrc").is_symlink());
+        assert!(dotfiles
+            .check_untrack(&config, &home.join(".vimrc"))
+            .is_err());
+
+        let dotfiles = dotfiles
+            .track(&config, &home.join(".vimrc"), |_, _| Ok(()))
+            .unwrap();
+        assert!(dotfiles
+            .check_track(&config, &home.join(".vimrc"), |_, _| Ok(()))
+            .is_err());
+        assert_eq!(
+            dotfiles
+                .check_set_executable(&config, &home.join(".vimrc"), Executable::Yes)
+                .unwrap(),
+            relative
+        );
+    }
+
+    #[test]
+    fn test_move_ignored() {
+        let (_dir, config) = setup_config();
+        let home = config.get_home().unwrap();
+        fs::create_dir_all(home.join(".emacs.d/cache")).unwrap();
+        setup_dotfile(&config, ".emacs.d/init.el");
+        setup_dotfile(&config, ".emacs.d/cache/file");
+        setup_dotfile(&config, ".emacs.d/emacs.lock");
+        // the patterns are added after tracking, which keeps ignored content in place
+        let dotfiles = Dotfiles::new(Some(vec![]), None, Some(vec![]))
+            .track(&config, &home.join(".emacs.d"), |_, _| Ok(()))
+            .unwrap();
+        let dotfiles = Dotfiles {
+            ignore: vec!["*.lock".to_string()],
+            entry_ignore: BTreeMap::from([(PathBuf::from(".emacs.d"), vec!["cache/".to_string()])]),
+            ..dotfiles
+        };
+        check(&dotfiles, &config).unwrap();
+        assert_eq!(
+            dotfiles.get_ignored_files(&config.contents()).unwrap(),
+            vec![
+                PathBuf::from(".emacs.d/cache"),
+                PathBuf::from(".emacs.d/emacs.lock")
+            ]
+        );
+
+        let dotfiles = dotfiles.move_ignored(&config, |_, _| Ok(())).unwrap();
+        check(&dotfiles, &config).unwrap();
+        assert_eq!(dotfiles.get_unfolded(), vec![PathBuf::from(".emacs.d")]);
+        assert!(dotfiles
+            .get_ignored_files(&config.contents())
+            .unwrap()
+            .is_empty());
+        assert!(home.join(".emacs.d/init.el").is_symlink());
+        assert!(home.join(".emacs.d/cache/file").is_file());
+        assert!(!home.join(".emacs.d/cache/file").is_symlink());
+        assert!(!home.join(".emacs.d/emacs.lock").is_symlink());
+        assert!(!config.contents().join(".emacs.d/cache").exists());
+    }
+
+    #[test]
+    fn test_untrack_unfolded() {
+        let (_dir, config) = setup_config();
+        let home = config.get_home().unwrap();
+        fs::create_dir_all(home.join(".emacs.d/lisp")).unwrap();
+        setup_dotfile(&config, ".emacs.d/init.el");
+        setup_dotfile(&config, ".emacs.d/lisp/mode.el");
+        let dir = home.join(".emacs.d");
+        let dotfiles = Dotfiles::new(Some(vec![]), None, Some(vec![]))
+            .track(&config, &dir, |_, _| Ok(()))
+            .unwrap()
+            .unfold(&config, &dir)
+            .unwrap();
+        fs::write(dir.join("history"), "").unwrap();
+
+        let dotfiles = dotfiles.untrack(&config, &dir, |_| Ok(())).unwrap();
+        check(&dotfiles, &config).unwrap();
+        assert!(dotfiles.get_unfolded().is_empty());
+        assert_eq!(dotfiles.get_deleted(), vec![PathBuf::from(".emacs.d")]);
+        assert!(!config.contents().join(".emacs.d").exists());
+        assert!(!dir.join("lisp").exists());
+        assert!(!dir.join("init.el").exists());
+        // untracked files are kept
+        assert!(dir.join("history").is_file());
+    }
+
+    #[test]
+    fn test_track_ignored() {
+        let (_dir, config) = setup_config();
+        let home = config.get_home().unwrap();
+        fs::create_dir_all(home.join(".emacs.d/cache")).unwrap();
+        fs::create_dir_all(home.join(".emacs.d/lisp")).unwrap();
+        setup_dotfile(&config, ".emacs.d/init.el");
+        setup_dotfile(&config, ".emacs.d/lisp/mode.el");
+        setup_dotfile(&config, ".emacs.d/cache/file");
+        let dotfiles = Dotfiles {
+            entry_ignore: BTreeMap::from([(PathBuf::from(".emacs.d"), vec!["cache/".to_string()])]),
+            ..Dotfiles::new(Some(vec![]), None, Some(vec![]))
+        };
+        let dotfiles = dotfiles
+            .track(&config, &home.join(".emacs.d"), |_, _| Ok(()))
+            .unwrap();
+        check(&dotfiles, &config).unwrap();
+        assert_eq!(dotfiles.get_files(), vec![PathBuf::from(".emacs.d")]);
+        assert_eq!(dotfiles.get_unfolded(), vec![PathBuf::from(".emacs.d")]);
+        assert!(home.join(".emacs.d/init.el").is_symlink());
+        assert!(home.join(".emacs.d/lisp/mode.el").is_symlink());
+        assert!(!home.join(".emacs.d/cache").is_symlink());
+        assert!(home.join(".emacs.d/cache/file").is_file());
+        assert!(config.contents().join(".emacs.d/lisp/mode.el").is_file());
+        assert!(!config.contents().join(".emacs.d/cache").exists());
+    }
+
+    #[test]
+    fn test_repair_owner_denied() {
+        let path = Path::new("/etc/hosts");
+        let root = "0:0".parse::<Owner>().unwrap();
+        let denied = std::io::Error::from(ErrorKind::PermissionDenied);
+        assert_eq!(
+            owner_repair_result(path, &root, Err(denied)).unwrap(),
+            RepairResult::Skipped
+        );
+        assert_eq!(
+            owner_repair_result(path, &root, Ok(())).unwrap(),
+            RepairResult::Successful
+        );
+        let missing = std::io::Error::from(ErrorKind::NotFound);
+        assert!(owner_repair_result(path, &root, Err(missing)).is_err());
+
+        // without root privileges, the owner cannot be changed to root
+        if !is_root() {
+            let (_dir, config) = setup_config();
+            let file = setup_dotfile(&config, ".vimrc");
+            let dotfiles = Dotfiles::new(Some(vec![]), None, Some(vec![]))
+                .track(&config, &file, |_, _| Ok(()))
+                .unwrap();
+            let dotfiles = Dotfiles {
+                owners: BTreeMap::from([(PathBuf::from(".vimrc"), root)]),
+                ..dotfiles
+            };
+            assert_eq!(
+                dotfiles
+                    .repair(&config, |_| Ok(RepairAction::Skip))
+                    .unwrap(),
+                RepairResult::Skipped
+            );
+        }
+    }
+
+    #[test]
+    fn test_owners() {
+        let (_dir, config) = setup_config();
+        let home = config.get_home().unwrap();
+        let path = setup_dotfile(&config, ".vimrc");
+        let dotfiles = Dotfiles::new(Some(vec![]), None, Some(vec![]))
+            .track(&config, &path, |_, _| Ok(()))
+            .unwrap();
+        let actual = Owner::get(&config.contents().join(".vimrc")).unwrap();
+
+        let dotfiles = dotfiles.set_owner(&config, &path, Some(actual)).unwrap();
+        check(&dotfiles, &config).unwrap();
+        assert!(dotfiles.get_owner_mismatches(&config).unwrap().is_empty());
+        assert!(dotfiles
+            .set_owner(&config, &home.join(".missing"), Some(actual))
+            .is_err());
+
+        let other = Owner {
+            uid: actual.uid.map(|uid| uid + 1),
+            gid: None,
+        };
+        let mismatching = Dotfiles {
+            owners: BTreeMap::from([(PathBuf::from(".vimrc"), other)]),
+            ..dotfiles.canonicalize()
+        };
+        assert!(check(&mismatching, &config).is_err());
+        assert_eq!(mismatching.get_owner_mismatches(&config).unwrap().len(), 1);
+
+        let dotfiles = dotfiles.untrack(&config, &path, |_| Ok(())).unwrap();
+        assert!(dotfiles.get_owners().is_empty());
+        check(&dotfiles, &config).unwrap();
+    }
+
+    #[test]
+    fn test_system_owners() {
+        let (dir, _) = setup_config();
+        let config = crate::config::Sources::new(dir.path().join("config.toml"), true, None, None)
+            .with_system_scope(true)
+            .load()
+            .unwrap();
+        let dotfiles = Dotfiles::new(Some(vec![PathBuf::from("etc/hosts")]), None, Some(vec![]));
+        let file = PathBuf::from("/etc/hosts");
+        let check_set_owner =
+            |owner: &str| dotfiles.check_set_owner(&config, &file, Some(owner.parse().unwrap()));
+        assert_eq!(
+            check_set_owner("0:100").unwrap(),
+            PathBuf::from("etc/hosts")
+        );
+        assert!(check_set_owner(":100").is_ok());
+        assert!(check_set_owner("1000").is_err());
+        assert!(dotfiles.check_set_owner(&config, &file, None).is_ok());
+    }
+
+    #[test]
+    fn test_rebase_links() {
+        let dotfiles = Dotfiles {
+            links: BTreeMap::from([
+                (PathBuf::from(".local"), PathBuf::from("/old/data/local")),
+                (PathBuf::from(".shared"), PathBuf::from("/srv/shared")),
+            ]),
+            ..Dotfiles::new(None, None, None)
+        };
+        assert_eq!(
+            dotfiles
+                .rebase_links(Path::new("/old"), Path::new("/new"))
+                .get_links(),
+            BTreeMap::from([
+                (PathBuf::from(".local"), PathBuf::from("/new/data/local")),
+                (PathBuf::from(".shared"), PathBuf::from("/srv/shared")),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_rebase_include() {
+        let dotfiles = Dotfiles {
+            include: vec![
+                PathBuf::from("../shared/dotfiles.toml"),
+                PathBuf::from("team/dotfiles.toml"),
+                PathBuf::from("/old/extra/dotfiles.toml"),
+            ],
+            ..Dotfiles::new(None, None, None)
+        };
+        let old = Path::new("/old/target");
+        let new = Path::new("/new/nested/target");
+        assert_eq!(
+            dotfiles
+                .rebase_include(old, new, Path::new("/old/target"), new)
+                .get_include(),
+            vec![
+                PathBuf::from("../../../old/shared/dotfiles.toml"),
+                PathBuf::from("team/dotfiles.toml"),
+                PathBuf::from("/old/extra/dotfiles.toml"),
+            ]
+        );
+    }
 }
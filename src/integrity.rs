@@ -0,0 +1,206 @@
+use crate::config::*;
+use crate::dotfiles::*;
+use crate::util::*;
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Change {
+    Modified,
+    Added,
+    Removed,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let str = match self {
+            Change::Modified => "modified",
+            Change::Added => "added",
+            Change::Removed => "removed",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+// keyed by the path relative to the contents directory, directories are hashed file by file
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Checksums {
+    version: Option<i64>, // always 1
+    files: BTreeMap<PathBuf, String>,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn hash(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    if path.symlink_metadata()?.file_type().is_symlink() {
+        // symlinks inside tracked directories are hashed by their target
+        hasher.update(b"symlink\0");
+        hasher.update(path.read_link()?.as_os_str().as_bytes());
+    } else {
+        let mut file = File::open(path)?;
+        let mut buffer = [0; 8192];
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+impl Checksums {
+    pub fn compute(config: &Config, dotfiles: &Dotfiles) -> Result<Checksums> {
+        let contents = config.contents();
+        let mut files = BTreeMap::new();
+        for dotfile in dotfiles.get_files() {
+            let path = contents.join(&dotfile);
+            if path.symlink_metadata()?.is_dir() {
+                for entry in walk(&path)? {
+                    if !path.join(&entry).symlink_metadata()?.is_dir() {
+                        files.insert(dotfile.join(&entry), hash(&path.join(entry))?);
+                    }
+                }
+            } else {
+                files.insert(dotfile, hash(&path)?);
+            }
+        }
+        Ok(Checksums {
+            version: Some(1),
+            files,
+        })
+    }
+
+    pub fn load(config: &Config) -> Result<Option<Checksums>> {
+        let path = config.checksums();
+        if !path.exists() {
+            return Ok(None);
+        }
+        let mut contents = String::new();
+        File::open(path)?.read_to_string(&mut contents)?;
+        let checksums = toml::from_str::<Checksums>(contents.as_ref())?;
+        match checksums.version {
+            None | Some(1) => Ok(Some(checksums)),
+            Some(version) => Err(anyhow!("Invalid checksums version number {:?}", version))?,
+        }
+    }
+
+    pub fn save(&self, config: &Config) -> Result<()> {
+        write_atomically(&config.checksums(), &toml::to_string(self)?)
+    }
+
+    pub fn record(config: &Config, dotfiles: &Dotfiles) -> Result<()> {
+        info!("Recording checksums in {:?}", config.checksums());
+        Checksums::compute(config, dotfiles)?.save(config)
+    }
+
+    pub fn diff(&self, actual: &Checksums) -> Vec<(PathBuf, Change)> {
+        let mut changes = vec![];
+        for (file, checksum) in &self.files {
+            match actual.files.get(file) {
+                Some(other) if other == checksum => {}
+                Some(_) => changes.push((file.clone(), Change::Modified)),
+                None => changes.push((file.clone(), Change::Removed)),
+            }
+        }
+        for file in actual.files.keys() {
+            if !self.files.contains_key(file) {
+                changes.push((file.clone(), Change::Added));
+            }
+        }
+        changes.sort();
+        changes
+    }
+
+    pub fn check(config: &Config, dotfiles: &Dotfiles) -> Result<()> {
+        info!("Checking integrity of content in {:?}", config.contents());
+        let actual = Checksums::compute(config, dotfiles)?;
+        match Checksums::load(config)? {
+            None => {
+                warn!("No checksums recorded yet, recording current state");
+                actual.save(config)?;
+            }
+            Some(recorded) => {
+                let changes = recorded.diff(&actual);
+                if changes.is_empty() {
+                    info!("{} file(s) unchanged.", actual.files.len());
+                } else {
+                    let changes = changes
+                        .iter()
+                        .map(|(file, change)| format!("{:?} ({})", file, change))
+                        .collect::<Vec<_>>();
+                    Err(anyhow!(
+                        "Content changed outside of {}: {}",
+                        APP_NAME,
+                        changes.join(", ")
+                    ))?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::test_util::*;
+    use crate::integrity::*;
+    use std::fs;
+    use std::io::Write;
+
+    #[test]
+    fn test_integrity() {
+        let (_dir, config) = setup_config();
+        let contents = config.contents();
+        fs::create_dir(contents.join(".dir")).unwrap();
+        File::create(contents.join(".dir/file")).unwrap();
+        File::create(contents.join(".file")).unwrap();
+        let dotfiles = Dotfiles::new(
+            Some(vec![PathBuf::from(".dir"), PathBuf::from(".file")]),
+            None,
+            Some(vec![]),
+        );
+
+        Checksums::record(&config, &dotfiles).unwrap();
+        Checksums::check(&config, &dotfiles).unwrap();
+
+        File::create(contents.join(".file"))
+            .unwrap()
+            .write_all(b"tampered")
+            .unwrap();
+        File::create(contents.join(".dir/new")).unwrap();
+        fs::remove_file(contents.join(".dir/file")).unwrap();
+        let recorded = Checksums::load(&config).unwrap().unwrap();
+        let actual = Checksums::compute(&config, &dotfiles).unwrap();
+        assert_eq!(
+            recorded.diff(&actual),
+            vec![
+                (PathBuf::from(".dir/file"), Change::Removed),
+                (PathBuf::from(".dir/new"), Change::Added),
+                (PathBuf::from(".file"), Change::Modified),
+            ]
+        );
+        assert!(Checksums::check(&config, &dotfiles).is_err());
+    }
+
+    #[test]
+    fn test_hash() {
+        let (_dir, config) = setup_config();
+        let path = config.contents().join("file");
+        File::create(&path).unwrap().write_all(b"abc").unwrap();
+        assert_eq!(
+            hash(&path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
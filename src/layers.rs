@@ -0,0 +1,450 @@
+use crate::config::*;
+use crate::dotfiles::*;
+use crate::paths;
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+pub struct Layer {
+    pub name: String,
+    pub config: Config,
+    pub dotfiles: Dotfiles,
+    // index of the configured target, shared by a manifest and all manifests it includes
+    priority: usize,
+}
+
+impl Layer {
+    // loads the manifest of the target and all included manifests, which are named after their
+    // directory just like targets
+    fn load(config: &Config, target: PathBuf, priority: usize) -> Result<Vec<Layer>> {
+        let layers = Dotfiles::load_all(&config.layer(target))?
+            .into_iter()
+            .map(|(config, dotfiles)| {
+                let target = config.target();
+                let name = match target.file_name() {
+                    Some(name) => name.to_string_lossy().into_owned(),
+                    None => target.to_string_lossy().into_owned(),
+                };
+                Layer {
+                    name,
+                    config,
+                    dotfiles,
+                    priority,
+                }
+            })
+            .collect();
+        Ok(layers)
+    }
+
+    // entries that are backed by content or a symlink, as opposed to declared directories
+    fn get_linked(&self) -> Vec<PathBuf> {
+        let mut linked = self.dotfiles.get_files();
+        linked.extend(self.dotfiles.get_links().into_keys());
+        linked
+    }
+}
+
+// ordered from highest to lowest priority, included manifests directly follow their target
+pub struct Layers {
+    layers: Vec<Layer>,
+}
+
+impl Layers {
+    pub fn load(config: &Config) -> Result<Layers> {
+        let mut layers = vec![];
+        for (priority, target) in config.targets().into_iter().enumerate() {
+            layers.extend(Layer::load(config, target, priority)?);
+        }
+        Ok(Layers { layers })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Layer> {
+        self.layers.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn get(&self, name: Option<&str>) -> Result<&Layer> {
+        match name {
+            None => Ok(&self.layers[0]),
+            Some(name) => {
+                let matching = self
+                    .layers
+                    .iter()
+                    .filter(|layer| {
+                        layer.name == name
+                            || layer.config.target() == Path::new(name)
+                            || layer.config.dotfiles() == Path::new(name)
+                    })
+                    .collect::<Vec<_>>();
+                match matching.as_slice() {
+                    [layer] => Ok(layer),
+                    [] => Err(anyhow!("Unknown layer {:?}", name))?,
+                    _ => Err(anyhow!(
+                        "Ambiguous layer {:?}, please specify the target instead",
+                        name
+                    ))?,
+                }
+            }
+        }
+    }
+
+    pub fn resolve(&self, path: &Path) -> Result<PathBuf> {
+        let home = self.layers[0].config.get_home()?;
+        let entries = self
+            .layers
+            .iter()
+            .flat_map(|layer| layer.dotfiles.get_entries())
+            .collect::<Vec<_>>();
+        paths::resolve(&home, &std::env::current_dir()?, &entries, path)
+    }
+
+    pub fn expand(&self, patterns: &[PathBuf]) -> Result<Vec<PathBuf>> {
+        let home = self.layers[0].config.get_home()?;
+        let entries = self
+            .layers
+            .iter()
+            .flat_map(|layer| layer.dotfiles.get_entries())
+            .collect::<Vec<_>>();
+        let mut paths = vec![];
+        for pattern in patterns {
+            // plain paths are relative to the working directory like in a shell, while globs
+            // are matched against the home directory and the entries of all layers
+            let expanded = if paths::is_glob(pattern) {
+                paths::expand(&home, &entries, pattern)?
+            } else {
+                vec![self.resolve(pattern)?]
+            };
+            for path in expanded {
+                if !paths.contains(&path) {
+                    paths.push(path);
+                }
+            }
+        }
+        if paths.is_empty() {
+            Err(anyhow!("No paths given"))?
+        }
+        Ok(paths)
+    }
+
+    // or containing entries below it, defaults to the layer with the highest priority
+    pub fn supplying(&self, file: &Path) -> Result<&Layer> {
+        let home = self.layers[0].config.get_home()?;
+        let relative = match paths::home_relative(&home, &std::path::absolute(file)?) {
+            Some(relative) => relative,
+            None => return Ok(&self.layers[0]),
+        };
+        let layer = self
+            .layers
+            .iter()
+            .find(|layer| layer.dotfiles.get_entries().contains(&relative))
+            .or_else(|| {
+                self.layers.iter().find(|layer| {
+                    layer
+                        .dotfiles
+                        .get_entries()
+                        .iter()
+                        .any(|entry| paths::overlaps(entry, &relative))
+                })
+            });
+        Ok(layer.unwrap_or(&self.layers[0]))
+    }
+
+    // for each layer, the entries supplied by a layer with higher priority
+    pub fn get_shadowed(&self) -> Vec<Vec<PathBuf>> {
+        self.layers
+            .iter()
+            .map(|layer| {
+                let supplied = self
+                    .layers
+                    .iter()
+                    .filter(|other| other.priority < layer.priority)
+                    .flat_map(|other| other.dotfiles.get_entries())
+                    .collect::<Vec<_>>();
+                layer
+                    .dotfiles
+                    .get_entries()
+                    .into_iter()
+                    .filter(|entry| supplied.contains(entry))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn check_conflicts(&self) -> Result<()> {
+        for (i, higher) in self.layers.iter().enumerate() {
+            for lower in &self.layers[i + 1..] {
+                let overrides = higher.dotfiles.get_overrides();
+                for entry in lower.dotfiles.get_entries() {
+                    if !higher.dotfiles.get_entries().contains(&entry) {
+                        continue;
+                    }
+                    // a manifest and its includes are merged, so they cannot override each other
+                    if higher.priority == lower.priority {
+                        Err(anyhow!(
+                            "{:?} is defined by both {:?} and {:?}",
+                            entry,
+                            higher.config.dotfiles(),
+                            lower.config.dotfiles()
+                        ))?
+                    }
+                    if !overrides.contains(&entry) {
+                        Err(anyhow!(
+                            "{:?} is defined by the layers {} and {}, declare an override in {} if this is intended",
+                            entry,
+                            higher.name,
+                            lower.name,
+                            higher.name
+                        ))?
+                    }
+                }
+                for entry in lower.get_linked() {
+                    if let Some(other) = higher
+                        .get_linked()
+                        .iter()
+                        .find(|other| **other != entry && paths::overlaps(other, &entry))
+                    {
+                        Err(anyhow!(
+                            "{:?} of layer {} overlaps with {:?} of layer {}",
+                            entry,
+                            lower.name,
+                            other,
+                            higher.name
+                        ))?
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn check_contents(&self) -> Result<()> {
+        if self.layers.len() > 1 {
+            info!("Checking for conflicts between layers");
+            self.check_conflicts()?;
+        }
+        for layer in &self.layers {
+            layer.dotfiles.check_contents(&layer.config)?;
+        }
+        Ok(())
+    }
+
+    pub fn check(&self) -> Result<()> {
+        self.check_except(&[])
+    }
+
+    // e.g. entries whose symlinks are about to be removed anyway
+    pub fn check_except(&self, excluded: &[PathBuf]) -> Result<()> {
+        self.check_contents()?;
+        for (layer, shadowed) in self.layers.iter().zip(self.get_shadowed()) {
+            if self.layers.len() > 1 {
+                info!("Checking home directory for layer {}", layer.name);
+            }
+            layer
+                .dotfiles
+                .without(&[shadowed.as_slice(), excluded].concat())
+                .check_home(&layer.config)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_conflicts(&self) -> Result<Vec<PathBuf>> {
+        let mut conflicts = vec![];
+        for (layer, shadowed) in self.layers.iter().zip(self.get_shadowed()) {
+            for conflict in layer
+                .dotfiles
+                .without(&shadowed)
+                .get_conflicts(&layer.config)?
+            {
+                if !conflicts.contains(&conflict) {
+                    conflicts.push(conflict);
+                }
+            }
+        }
+        conflicts.sort();
+        Ok(conflicts)
+    }
+
+    pub fn repoint(&self, from: &Path, to: &Path) -> Result<()> {
+        for (layer, shadowed) in self.layers.iter().zip(self.get_shadowed()) {
+            layer
+                .dotfiles
+                .without(&shadowed)
+                .repoint(&layer.config, from, to)?;
+        }
+        Ok(())
+    }
+
+    pub fn unlink(&self) -> Result<()> {
+        for (layer, shadowed) in self.layers.iter().zip(self.get_shadowed()) {
+            layer.dotfiles.without(&shadowed).unlink(&layer.config)?;
+        }
+        Ok(())
+    }
+
+    pub fn eject(&self) -> Result<()> {
+        for (layer, shadowed) in self.layers.iter().zip(self.get_shadowed()) {
+            layer.dotfiles.without(&shadowed).eject(&layer.config)?;
+        }
+        Ok(())
+    }
+
+    pub fn repair(
+        &self,
+        wrong_behaviour: fn(&PathBuf) -> Result<RepairAction>,
+    ) -> Result<RepairResult> {
+        let mut results = vec![];
+        for (layer, shadowed) in self.layers.iter().zip(self.get_shadowed()) {
+            results.push(
+                layer
+                    .dotfiles
+                    .without(&shadowed)
+                    .repair(&layer.config, wrong_behaviour)?,
+            );
+        }
+        Ok(RepairResult::coalesce_all(results))
+    }
+
+    pub fn check_untracked(&self, layer: &Layer, file: &Path) -> Result<()> {
+        let home = layer.config.get_home()?;
+        let file = std::path::absolute(file)?;
+        let resolved = file.canonicalize().ok();
+        for other in &self.layers {
+            if other.config.target() == layer.config.target() {
+                continue;
+            }
+            if let Some(ref resolved) = resolved {
+                if resolved.starts_with(other.config.contents()) {
+                    Err(anyhow!(
+                        "Cannot track {:?} because it is supplied by the layer {}",
+                        file,
+                        other.name
+                    ))?
+                }
+            }
+            let relative = match paths::home_relative(&home, &file) {
+                Some(relative) => relative,
+                None => continue,
+            };
+            if other.dotfiles.get_entries().contains(&relative) {
+                Err(anyhow!(
+                    "Cannot track {:?} because it is already tracked in the layer {}",
+                    relative,
+                    other.name
+                ))?
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::test_util::*;
+    use crate::config::Config;
+    use crate::layers::*;
+    use std::fs;
+    use std::fs::File;
+    use std::os::unix::fs as unix;
+
+    fn setup_layer(config: &Config, name: &str, files: &[&str], overrides: &[&str]) -> PathBuf {
+        let target = config.target().parent().unwrap().join(name);
+        fs::create_dir_all(target.join("contents")).unwrap();
+        let layer = config.layer(target.clone());
+        for file in files {
+            File::create(layer.contents().join(file)).unwrap();
+        }
+        let manifest = format!("files = {:?}\noverrides = {:?}\n", files, overrides);
+        fs::write(layer.dotfiles(), manifest).unwrap();
+        target
+    }
+
+    fn setup_layers(overrides: &[&str]) -> (tempfile::TempDir, Config) {
+        let (dir, config) = setup_config();
+        let high = setup_layer(&config, "personal", &[".vimrc"], overrides);
+        let low = setup_layer(&config, "team", &[".vimrc", ".bashrc"], &[]);
+        let contents = toml::to_string(&config).unwrap().replace(
+            &format!("{:?}", config.target()),
+            &format!("[{:?}, {:?}]", high, low),
+        );
+        let path = dir.path().join("config.toml");
+        fs::write(&path, contents).unwrap();
+        let config = Config::load(&path).unwrap();
+        let home = config.get_home().unwrap();
+        unix::symlink(high.join("contents/.vimrc"), home.join(".vimrc")).unwrap();
+        unix::symlink(low.join("contents/.bashrc"), home.join(".bashrc")).unwrap();
+        (dir, config)
+    }
+
+    #[test]
+    fn test_override() {
+        let (_dir, config) = setup_layers(&[".vimrc"]);
+        let layers = Layers::load(&config).unwrap();
+        layers.check().unwrap();
+        let home = config.get_home().unwrap();
+        assert_eq!(
+            layers.supplying(&home.join(".vimrc")).unwrap().name,
+            "personal"
+        );
+        assert_eq!(
+            layers.supplying(&home.join(".bashrc")).unwrap().name,
+            "team"
+        );
+        assert_eq!(layers.get(Some("team")).unwrap().name, "team");
+    }
+
+    #[test]
+    #[should_panic(expected = "is defined by the layers personal and team")]
+    fn test_conflict() {
+        let (_dir, config) = setup_layers(&[]);
+        Layers::load(&config).unwrap().check().unwrap();
+    }
+
+    fn setup_include(config: &Config, name: &str, files: &[&str], include: &[&str]) {
+        let target = config.target().parent().unwrap().join(name);
+        fs::create_dir_all(target.join("contents")).unwrap();
+        for file in files {
+            File::create(target.join("contents").join(file)).unwrap();
+        }
+        let manifest = format!("files = {:?}\ninclude = {:?}\n", files, include);
+        fs::write(target.join("dotfiles.toml"), manifest).unwrap();
+    }
+
+    #[test]
+    fn test_include() {
+        let (_dir, config) = setup_config();
+        setup_include(&config, "target", &[".vimrc"], &["../shared/dotfiles.toml"]);
+        setup_include(&config, "shared", &[".bashrc"], &[]);
+        let home = config.get_home().unwrap();
+        let shared = config.target().parent().unwrap().join("shared");
+        unix::symlink(config.contents().join(".vimrc"), home.join(".vimrc")).unwrap();
+        unix::symlink(shared.join("contents/.bashrc"), home.join(".bashrc")).unwrap();
+
+        let layers = Layers::load(&config).unwrap();
+        layers.check().unwrap();
+        assert_eq!(layers.len(), 2);
+        let layer = layers.supplying(&home.join(".bashrc")).unwrap();
+        assert_eq!(layer.name, "shared");
+        assert_eq!(layer.config.dotfiles(), shared.join("dotfiles.toml"));
+        assert_eq!(layers.get(None).unwrap().name, "target");
+    }
+
+    #[test]
+    #[should_panic(expected = "is defined by both")]
+    fn test_include_duplicate() {
+        let (_dir, config) = setup_config();
+        setup_include(&config, "target", &[".vimrc"], &["../shared/dotfiles.toml"]);
+        setup_include(&config, "shared", &[".vimrc"], &[]);
+        Layers::load(&config).unwrap().check().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "Cyclic include")]
+    fn test_include_cycle() {
+        let (_dir, config) = setup_config();
+        setup_include(&config, "target", &[], &["../shared/dotfiles.toml"]);
+        setup_include(&config, "shared", &[], &["../target/dotfiles.toml"]);
+        Layers::load(&config).unwrap();
+    }
+}
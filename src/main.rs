@@ -4,7 +4,9 @@ extern crate anyhow;
 extern crate clap;
 extern crate clap_complete;
 extern crate dirs;
-extern crate fs_extra;
+extern crate glob;
+extern crate ignore;
+extern crate libc;
 #[macro_use]
 extern crate log;
 extern crate pretty_env_logger;
@@ -13,26 +15,77 @@ extern crate pretty_env_logger;
 extern crate proptest;
 #[macro_use]
 extern crate serde_derive;
+extern crate sha2;
 #[cfg(test)]
 extern crate tempfile;
 extern crate toml;
+extern crate xattr;
 extern crate xdg;
 
 mod commands;
 mod config;
 mod dotfiles;
+mod integrity;
+mod layers;
 mod paths;
 mod perm;
+mod transfer;
 mod util;
 
 use anyhow::{Error, Result};
-use clap::App;
+use clap::{App, ArgMatches};
 use clap_complete::{generate, Shell};
 use log::LevelFilter;
 use std::io;
-use std::path::PathBuf;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
+// the paths given as arguments, followed by the ones read from stdin
+fn files(matches: &ArgMatches) -> Result<Vec<PathBuf>> {
+    let mut files = match matches.values_of("files") {
+        Some(values) => values.map(PathBuf::from).collect(),
+        None => vec![],
+    };
+    if matches.is_present("stdin") {
+        let mut input = vec![];
+        io::stdin().read_to_end(&mut input)?;
+        let separator = if matches.is_present("nul") {
+            b'\0'
+        } else {
+            b'\n'
+        };
+        files.extend(util::split_paths(&input, separator));
+    }
+    Ok(files)
+}
+
+// the mode of `executable`, which used to be given as a second positional argument, e.g.
+// `executable FILE false`. That form is still accepted unless a file named like the mode exists,
+// but deprecated.
+fn executable_mode(matches: &ArgMatches, files: &mut Vec<PathBuf>) -> Result<util::Executable> {
+    let positional = matches.values_of("files").map_or(0, |values| values.len());
+    // only `executable FILE BOOL` is the legacy form, where the second positional is the mode
+    let legacy = positional == 2
+        && matches!(files.get(1), Some(last)
+            if (last == Path::new("true") || last == Path::new("false"))
+                && last.symlink_metadata().is_err());
+    let mode = if legacy {
+        let mode = files.remove(1);
+        if matches.is_present("mode") {
+            Err(anyhow!(
+                "The mode cannot be given both as {:?} and with --mode",
+                mode
+            ))?
+        }
+        warn!("Passing the mode as a positional argument is deprecated, use --mode instead");
+        mode.to_string_lossy().into_owned()
+    } else {
+        matches.value_of("mode").unwrap_or("true").to_owned()
+    };
+    Ok((mode == "true").into())
+}
+
 fn exec() -> Result<()> {
     let mut builder = pretty_env_logger::formatted_builder();
     builder.filter(None, LevelFilter::Debug);
@@ -47,21 +100,85 @@ fn exec() -> Result<()> {
     let matches = cli.clone().get_matches();
     let force = matches.is_present("force");
 
-    let config = matches
-        .value_of("config")
-        .map(PathBuf::from)
-        .ok_or(() /* dummy */)
-        .or_else(|()| config::get_path())?;
+    let config = config::Sources::new(
+        matches
+            .value_of("config")
+            .map(PathBuf::from)
+            .ok_or(() /* dummy */)
+            .or_else(|()| config::get_path())?,
+        matches.is_present("config"),
+        matches.value_of("target_override").map(PathBuf::from),
+        matches.value_of("home").map(PathBuf::from),
+    )
+    .with_system_scope(matches.is_present("system"));
+
+    if matches.is_present("system") {
+        if !perm::is_root() {
+            Err(anyhow!("--system requires root privileges"))?
+        }
+        if let Some(cmd @ ("init" | "bootstrap" | "relocate" | "migrate-home")) =
+            matches.subcommand_name()
+        {
+            Err(anyhow!("--system cannot be used with {}", cmd))?
+        }
+    }
 
     if let Some((cmd, matches)) = matches.subcommand() {
         match cmd {
             "init" => commands::init(
                 &config,
                 &PathBuf::from(matches.value_of("target").unwrap()),
-                matches.value_of("home").map(PathBuf::from),
+                matches.is_present("readme"),
+                matches.is_present("gitignore"),
+                force,
+            ),
+            "bootstrap" => commands::bootstrap(
+                &config,
+                &PathBuf::from(matches.value_of("target").unwrap()),
+                matches.is_present("dry_run"),
+                force,
+            ),
+            "eject" => commands::eject(&config, matches.value_of("archive").map(Path::new), force),
+            "relocate" => commands::relocate(
+                &config,
+                Path::new(matches.value_of("path").unwrap()),
+                matches.value_of("layer"),
+            ),
+            "migrate-home" => commands::migrate_home(
+                &config,
+                Path::new(matches.value_of("home_dir").unwrap()),
+                matches.is_present("remove_old"),
+                force,
+            ),
+            "config" => match matches.subcommand() {
+                Some(("get", matches)) => {
+                    commands::config_get(&config, matches.value_of("key").unwrap())
+                }
+                Some(("set", matches)) => config.set(
+                    matches.value_of("key").unwrap(),
+                    &matches
+                        .values_of("value")
+                        .unwrap()
+                        .map(PathBuf::from)
+                        .collect::<Vec<_>>(),
+                ),
+                Some(("unset", matches)) => config.unset(matches.value_of("key").unwrap()),
+                Some(("validate", _)) => commands::config_validate(&config),
+                Some(("show", matches)) => {
+                    commands::config_show(&config, matches.is_present("origin"))
+                }
+                _ => {
+                    cli.print_help()?;
+                    Ok(())
+                }
+            },
+            "check" => commands::check(
+                &config,
+                matches.is_present("repair"),
+                matches.is_present("integrity"),
+                matches.is_present("move_ignored"),
                 force,
             ),
-            "check" => commands::check(&config, matches.is_present("repair"), force),
             "completions" => {
                 let shell = matches.value_of("shell").unwrap();
                 generate(
@@ -73,24 +190,47 @@ fn exec() -> Result<()> {
                 Ok(())
             }
             "list" => commands::list(&config),
+            "status" => commands::status(&config),
             "track" => commands::track(
                 &config,
-                &PathBuf::from(matches.value_of("file").unwrap()),
+                &files(matches)?,
+                matches.value_of("layer"),
                 matches.is_present("skip_check"),
                 force,
             ),
-            "untrack" => commands::untrack(
+            "adopt-orphans" => commands::adopt_orphans(&config, force),
+            "untrack" => {
+                if matches.is_present("stdin") && !force {
+                    Err(anyhow!(
+                        "Reading paths from stdin requires --force, since deletions cannot be confirmed"
+                    ))?
+                }
+                commands::untrack(&config, &files(matches)?, force)
+            }
+            "mv" => commands::mv(
                 &config,
-                &PathBuf::from(matches.value_of("file").unwrap()),
+                &PathBuf::from(matches.value_of("from").unwrap()),
+                &PathBuf::from(matches.value_of("to").unwrap()),
                 force,
             ),
-            "executable" => commands::set_executable(
+            "merge" => commands::merge(&config, &PathBuf::from(matches.value_of("file").unwrap())),
+            "fold" => commands::fold(&config, &PathBuf::from(matches.value_of("dir").unwrap())),
+            "unfold" => commands::unfold(&config, &PathBuf::from(matches.value_of("dir").unwrap())),
+            "dir" => commands::dir(
+                &config,
+                &PathBuf::from(matches.value_of("dir").unwrap()),
+                matches.value_of("mode").map(str::parse).transpose()?,
+                matches.is_present("remove"),
+            ),
+            "executable" => {
+                let mut files = files(matches)?;
+                let mode = executable_mode(matches, &mut files)?;
+                commands::set_executable(&config, &files, mode)
+            }
+            "owner" => commands::set_owner(
                 &config,
-                &PathBuf::from(matches.value_of("file").unwrap()),
-                match matches.value_of("mode") {
-                    None | Some("true") => util::Executable::Yes,
-                    _ => util::Executable::No,
-                },
+                &files(matches)?,
+                matches.value_of("owner").map(str::parse).transpose()?,
             ),
             _ => {
                 cli.print_help()?;
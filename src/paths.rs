@@ -1,4 +1,6 @@
-use std::path::{Path, PathBuf};
+use anyhow::Result;
+use glob::{MatchOptions, Pattern};
+use std::path::{Component, Path, PathBuf};
 
 pub fn relative_to(from: &Path, to: &Path) -> PathBuf {
     fn go(buf: &mut PathBuf, from: &Path, to: &Path) {
@@ -15,10 +17,146 @@ pub fn relative_to(from: &Path, to: &Path) -> PathBuf {
     buf
 }
 
+// lexical, so only correct for paths without symlinks
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if normalized.file_name().is_some() => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+// also works for paths that do not exist (anymore)
+pub fn canonicalize_existing(path: &Path) -> PathBuf {
+    for ancestor in path.ancestors() {
+        if let Ok(canonical) = ancestor.canonicalize() {
+            return normalize(&canonical.join(path.strip_prefix(ancestor).unwrap()));
+        }
+    }
+    normalize(path)
+}
+
+// either may go through a symlinked home, the last component of `path` is not resolved
+pub fn home_relative(home: &Path, path: &Path) -> Option<PathBuf> {
+    let plain = path
+        .components()
+        .all(|component| matches!(component, Component::RootDir | Component::Normal(_)));
+    if plain {
+        if let Ok(relative) = path.strip_prefix(home) {
+            return Some(relative.to_path_buf());
+        }
+    }
+    let home = home.canonicalize().ok()?;
+    let path = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => canonicalize_existing(parent).join(name),
+        _ => canonicalize_existing(path),
+    };
+    path.strip_prefix(home).ok().map(Path::to_path_buf)
+}
+
+// accepts absolute paths, `~/...`, paths relative to `cwd` and entries relative to home
+pub fn resolve(home: &Path, cwd: &Path, entries: &[PathBuf], path: &Path) -> Result<PathBuf> {
+    let relative = if let Ok(rest) = path.strip_prefix("~") {
+        Some(normalize(rest))
+    } else if path.is_absolute() {
+        home_relative(home, path)
+    } else {
+        let candidate = cwd.join(path);
+        let from_cwd = home_relative(home, &candidate);
+        let listed = normalize(path);
+        // only paths that do not exist relative to the working directory may name an entry
+        if candidate.symlink_metadata().is_err() && entries.contains(&listed) {
+            Some(listed)
+        } else {
+            from_cwd
+        }
+    };
+    match relative {
+        Some(relative)
+            if !relative.as_os_str().is_empty()
+                && relative
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_))) =>
+        {
+            Ok(home.join(relative))
+        }
+        _ => Err(anyhow!(
+            "{:?} is not in the home directory {:?}",
+            path,
+            home
+        ))?,
+    }
+}
+
+pub fn is_glob(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
+}
+
+// besides existing files, the glob matches `entries`, whose symlinks may be missing
+pub fn expand(home: &Path, entries: &[PathBuf], pattern: &Path) -> Result<Vec<PathBuf>> {
+    let relative = if let Ok(rest) = pattern.strip_prefix("~") {
+        rest
+    } else if let Ok(rest) = pattern.strip_prefix(home) {
+        rest
+    } else if pattern.is_absolute() {
+        Err(anyhow!(
+            "{:?} is not in the home directory {:?}",
+            pattern,
+            home
+        ))?
+    } else {
+        pattern
+    };
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        Err(anyhow!(
+            "{:?} must only consist of normal path components",
+            pattern
+        ))?
+    }
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    let glob = Pattern::new(&relative.to_string_lossy())?;
+
+    let mut matches = vec![];
+    let absolute = Pattern::escape(&home.to_string_lossy()) + "/" + glob.as_str();
+    for path in glob::glob_with(&absolute, options)? {
+        matches.push(path?);
+    }
+    for entry in entries {
+        let path = home.join(entry);
+        if glob.matches_path_with(entry, options) && !matches.contains(&path) {
+            matches.push(path);
+        }
+    }
+    if matches.is_empty() {
+        Err(anyhow!("{:?} does not match any file", pattern))?
+    }
+    matches.sort();
+    Ok(matches)
+}
+
+pub fn overlaps(left: &Path, right: &Path) -> bool {
+    left.starts_with(right) || right.starts_with(left)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::paths::*;
+    use std::fs;
+    use std::os::unix::fs as unix;
     use std::path::{Component, Path};
+    use tempfile::TempDir;
 
     fn canonicalize_light<P: AsRef<Path>>(path: P) -> PathBuf {
         let mut buf = PathBuf::new();
@@ -63,6 +201,109 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_home_relative() {
+        let dir = TempDir::new().unwrap();
+        let real = dir.path().join("data/user");
+        fs::create_dir_all(real.join(".config")).unwrap();
+        let home = dir.path().join("home");
+        unix::symlink(&real, &home).unwrap();
+
+        let expected = Some(PathBuf::from(".config/app"));
+        assert_eq!(home_relative(&home, &home.join(".config/app")), expected);
+        assert_eq!(home_relative(&home, &real.join(".config/app")), expected);
+        assert_eq!(home_relative(&real, &home.join(".config/app")), expected);
+        assert_eq!(home_relative(&home, &dir.path().join("data/.vimrc")), None);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let dir = TempDir::new().unwrap();
+        let home = dir.path().join("home");
+        fs::create_dir_all(home.join(".config")).unwrap();
+        fs::write(home.join(".config/existing"), "").unwrap();
+        let entries = vec![PathBuf::from(".vimrc")];
+        let resolve = |cwd: &Path, path: &str| resolve(&home, cwd, &entries, Path::new(path));
+
+        let expected = home.join(".vimrc");
+        assert_eq!(resolve(dir.path(), "~/.vimrc").unwrap(), expected);
+        assert_eq!(
+            resolve(dir.path(), &expected.to_string_lossy()).unwrap(),
+            expected
+        );
+        // listed in the manifest, but the symlink is missing
+        assert_eq!(resolve(dir.path(), ".vimrc").unwrap(), expected);
+        assert_eq!(resolve(&home.join(".config"), ".vimrc").unwrap(), expected);
+        assert_eq!(
+            resolve(&home.join(".config"), "../.vimrc").unwrap(),
+            expected
+        );
+        assert_eq!(
+            resolve(&home.join(".config"), "existing").unwrap(),
+            home.join(".config/existing")
+        );
+        assert_eq!(
+            resolve(&home, "./.config/new/file").unwrap(),
+            home.join(".config/new/file")
+        );
+        assert!(resolve(dir.path(), "/etc/passwd").is_err());
+        assert!(resolve(dir.path(), "~").is_err());
+        assert!(resolve(&home, "../outside").is_err());
+        assert!(resolve(dir.path(), ".unlisted").is_err());
+        // an existing path outside the home directory is not taken as an entry
+        fs::write(dir.path().join(".vimrc"), "").unwrap();
+        assert!(resolve(dir.path(), ".vimrc").is_err());
+    }
+
+    #[test]
+    fn test_expand() {
+        let dir = TempDir::new().unwrap();
+        let home = dir.path();
+        fs::create_dir_all(home.join(".config/app")).unwrap();
+        fs::write(home.join(".config/app/config"), "").unwrap();
+        fs::write(home.join(".config/other"), "").unwrap();
+        let entries = vec![PathBuf::from(".config/missing")];
+        let expand = |pattern: &str| expand(home, &entries, Path::new(pattern));
+
+        assert!(is_glob(Path::new(".config/*")));
+        assert!(!is_glob(Path::new(".config/app")));
+        let expected = vec![
+            home.join(".config/app"),
+            home.join(".config/missing"),
+            home.join(".config/other"),
+        ];
+        assert_eq!(expand(".config/*").unwrap(), expected);
+        assert_eq!(expand("~/.config/*").unwrap(), expected);
+        assert_eq!(
+            expand(&home.join(".config/*").to_string_lossy()).unwrap(),
+            expected
+        );
+        assert_eq!(
+            expand(".config/*/config").unwrap(),
+            vec![home.join(".config/app/config")]
+        );
+        assert!(expand(".local/*").is_err());
+        assert!(expand("../*").is_err());
+        assert!(expand("/etc/*").is_err());
+    }
+
+    #[test]
+    fn test_overlaps() {
+        assert!(overlaps(
+            Path::new(".config/foo"),
+            Path::new(".config/foo/bar")
+        ));
+        assert!(overlaps(
+            Path::new(".config/foo/bar"),
+            Path::new(".config/foo")
+        ));
+        assert!(overlaps(Path::new(".vimrc"), Path::new(".vimrc")));
+        assert!(!overlaps(
+            Path::new(".config/foo"),
+            Path::new(".config/foobar")
+        ));
+    }
+
     #[test]
     fn test_canonicalize_light() {
         assert_eq!(
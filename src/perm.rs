@@ -1,8 +1,13 @@
 use anyhow::{Error, Result};
 use bitflags::bitflags;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
 use std::fs::Permissions;
-use std::os::unix::fs::PermissionsExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+use std::str::FromStr;
 
 bitflags! {
     #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -75,6 +80,15 @@ impl Perms {
 }
 
 pub const MODE_MASK: u32 = 0o7777;
+// setuid, setgid and sticky bits
+pub const SPECIAL_MASK: u32 = 0o7000;
+
+impl Perms {
+    // ignoring setuid, setgid and sticky bits
+    pub fn from_mode(mode: u32) -> Perms {
+        Perms::from_bits_truncate((mode & !SPECIAL_MASK & MODE_MASK) as u16)
+    }
+}
 
 impl TryFrom<Permissions> for Perms {
     type Error = Error;
@@ -94,20 +108,198 @@ impl From<Perms> for Permissions {
     }
 }
 
+impl FromStr for Perms {
+    type Err = Error;
+
+    fn from_str(str: &str) -> Result<Self> {
+        let mode = u16::from_str_radix(str.trim_start_matches("0o"), 8)
+            .map_err(|err| anyhow!("Invalid mode {:?}: {}", str, err))?;
+        match Perms::from_bits(mode) {
+            Some(perms) => Ok(perms),
+            None => Err(anyhow!(
+                "Invalid mode {:?}, only permission bits are supported",
+                str
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Perms {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04o}", self.bits())
+    }
+}
+
+// modes are stored as octal strings, e.g. "0700"
+impl Serialize for Perms {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Perms {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let str = String::deserialize(deserializer)?;
+        str.parse().map_err(de::Error::custom)
+    }
+}
+
+// either part may be unspecified, e.g. "1000:1000", "0" or ":100"
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Owner {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl Owner {
+    // does not follow symlinks
+    pub fn get(path: &Path) -> Result<Owner> {
+        let meta = path.symlink_metadata()?;
+        Ok(Owner {
+            uid: Some(meta.uid()),
+            gid: Some(meta.gid()),
+        })
+    }
+
+    pub fn matches(&self, actual: &Owner) -> bool {
+        self.uid.is_none_or(|uid| actual.uid == Some(uid))
+            && self.gid.is_none_or(|gid| actual.gid == Some(gid))
+    }
+
+    pub fn set(&self, path: &Path) -> std::io::Result<()> {
+        std::os::unix::fs::lchown(path, self.uid, self.gid)
+    }
+}
+
+impl FromStr for Owner {
+    type Err = Error;
+
+    fn from_str(str: &str) -> Result<Self> {
+        let (uid, gid) = str.split_once(':').unwrap_or((str, ""));
+        let parse = |id: &str| {
+            if id.is_empty() {
+                Ok(None)
+            } else {
+                id.parse::<u32>()
+                    .map(Some)
+                    .map_err(|err| anyhow!("Invalid owner {:?}: {}", str, err))
+            }
+        };
+        let owner = Owner {
+            uid: parse(uid)?,
+            gid: parse(gid)?,
+        };
+        if owner == Owner::default() {
+            Err(anyhow!("Invalid owner {:?}, expected uid:gid", str))?
+        }
+        Ok(owner)
+    }
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(uid) = self.uid {
+            write!(f, "{}", uid)?;
+        }
+        if let Some(gid) = self.gid {
+            write!(f, ":{}", gid)?;
+        }
+        Ok(())
+    }
+}
+
+// owners are stored as strings, e.g. "1000:1000"
+impl Serialize for Owner {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Owner {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let str = String::deserialize(deserializer)?;
+        str.parse().map_err(de::Error::custom)
+    }
+}
+
+pub fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+// so that nobody but root can change what is applied to the system
+pub fn check_root_owned(path: &Path) -> Result<()> {
+    check_owned_by(path, 0)
+}
+
+fn check_owned_by(path: &Path, uid: u32) -> Result<()> {
+    let meta = check_entry_owned_by(path, uid)?;
+    if meta.is_dir() {
+        for entry in fs::read_dir(path)? {
+            check_owned_by(&entry?.path(), uid)?;
+        }
+    }
+    Ok(())
+}
+
+// checks `dir` and each of its ancestors up to `/`, both as given and with symlinks resolved, like
+// sshd's StrictModes. Otherwise whoever can write to an ancestor could replace `dir` afterwards
+pub fn check_root_owned_ancestors(dir: &Path) -> Result<()> {
+    check_ancestors_owned_by(dir, 0)
+}
+
+fn check_ancestors_owned_by(dir: &Path, uid: u32) -> Result<()> {
+    for path in [dir.canonicalize()?, std::path::absolute(dir)?] {
+        for ancestor in path.ancestors() {
+            check_entry_owned_by(ancestor, uid)?;
+        }
+    }
+    Ok(())
+}
+
+fn check_entry_owned_by(path: &Path, uid: u32) -> Result<fs::Metadata> {
+    let meta = path.symlink_metadata()?;
+    if meta.uid() != uid {
+        Err(anyhow!(
+            "{:?} is owned by uid {} instead of {}",
+            path,
+            meta.uid(),
+            if uid == 0 {
+                "root".to_string()
+            } else {
+                format!("uid {}", uid)
+            }
+        ))?
+    }
+    // the mode of symlinks is meaningless
+    let perms = Perms::from_bits_truncate((meta.mode() & MODE_MASK) as u16);
+    if !meta.file_type().is_symlink() && perms.intersects(Perms::GW | Perms::OW) {
+        Err(anyhow!(
+            "{:?} is writable by group or others (mode {})",
+            path,
+            perms
+        ))?
+    }
+    Ok(meta)
+}
+
 #[cfg(test)]
 mod test {
 
-    use crate::perm::{Perm, Perms};
+    use crate::perm::{
+        check_ancestors_owned_by, check_owned_by, check_root_owned, is_root, Owner, Perm, Perms,
+    };
+    use std::fs;
     use std::fs::Permissions;
     use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
 
-    fn all_perms<F: Fn(Perm) -> ()>(f: F) {
+    fn all_perms<F: Fn(Perm)>(f: F) {
         for p in 0..7 {
             f(Perm::from_bits_truncate(p))
         }
     }
 
-    fn all_permss<F: Fn(Perms) -> ()>(f: F) {
+    fn all_permss<F: Fn(Perms)>(f: F) {
         for p in 0..511 {
             f(Perms::from_bits_truncate(p))
         }
@@ -140,7 +332,7 @@ mod test {
     #[test]
     fn test_convert3() {
         all_permss(|perms| {
-            let lib: Permissions = perms.clone().into();
+            let lib: Permissions = perms.into();
             let actual: Perms = lib.try_into().unwrap();
             assert_eq!(perms, actual)
         })
@@ -193,9 +385,95 @@ mod test {
         Perms::try_from(Permissions::from_mode(0o1111)).unwrap();
     }
 
+    #[test]
+    fn test_from_mode() {
+        assert_eq!(Perms::from_mode(0o2755), Perms::from_mode(0o755));
+        assert_eq!(Perms::from_mode(0o41777).bits(), 0o777);
+    }
+
+    #[test]
+    fn test_parse() {
+        all_permss(|perms| assert_eq!(perms, perms.to_string().parse().unwrap()));
+        assert_eq!(Perms::UR | Perms::UW | Perms::UX, "700".parse().unwrap());
+        assert_eq!(Perms::UR | Perms::UW, "0o600".parse().unwrap());
+        assert!("1777".parse::<Perms>().is_err());
+        assert!("rwx".parse::<Perms>().is_err());
+    }
+
     #[test]
     fn test_unix_clear() {
         let result: Perms = Permissions::from_mode(0o10111).try_into().unwrap();
         assert_eq!(Perms::from_bits_truncate(0o111), result)
     }
+
+    #[test]
+    fn test_owner() {
+        let owner: Owner = "1000:100".parse().unwrap();
+        assert_eq!(owner.uid, Some(1000));
+        assert_eq!(owner.gid, Some(100));
+        for str in ["1000:100", "1000", ":100"] {
+            assert_eq!(str.parse::<Owner>().unwrap().to_string(), str);
+        }
+        assert_eq!("0:".parse::<Owner>().unwrap().to_string(), "0");
+        assert!("".parse::<Owner>().is_err());
+        assert!(":".parse::<Owner>().is_err());
+        assert!("root:wheel".parse::<Owner>().is_err());
+
+        let actual = Owner {
+            uid: Some(1000),
+            gid: Some(100),
+        };
+        assert!(owner.matches(&actual));
+        assert!(":100".parse::<Owner>().unwrap().matches(&actual));
+        assert!(!"0".parse::<Owner>().unwrap().matches(&actual));
+    }
+
+    #[test]
+    fn test_root_owned() {
+        let uid = unsafe { libc::geteuid() };
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("file");
+        fs::write(&file, "").unwrap();
+        fs::set_permissions(dir.path(), Permissions::from_mode(0o755)).unwrap();
+        fs::set_permissions(&file, Permissions::from_mode(0o644)).unwrap();
+        check_owned_by(dir.path(), uid).unwrap();
+        assert_eq!(check_root_owned(dir.path()).is_ok(), is_root());
+
+        fs::set_permissions(&file, Permissions::from_mode(0o664)).unwrap();
+        assert!(check_owned_by(dir.path(), uid).is_err());
+        fs::set_permissions(&file, Permissions::from_mode(0o644)).unwrap();
+        // anything below owned by someone else is rejected
+        assert!(check_owned_by(dir.path(), uid + 1).is_err());
+        if is_root() {
+            std::os::unix::fs::chown(&file, Some(1000), None).unwrap();
+            assert!(check_root_owned(dir.path()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_ancestors_owned() {
+        let uid = unsafe { libc::geteuid() };
+        let dir = TempDir::new().unwrap();
+        let parent = dir.path().join("parent");
+        let target = parent.join("target");
+        fs::create_dir_all(&target).unwrap();
+        fs::set_permissions(&target, Permissions::from_mode(0o755)).unwrap();
+        // the target itself is fine, but anyone may replace it through its parent
+        fs::set_permissions(&parent, Permissions::from_mode(0o777)).unwrap();
+        check_owned_by(&target, uid).unwrap();
+        let err = check_ancestors_owned_by(&target, uid).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains(&format!("{:?} is writable", parent)));
+
+        // the same through a symlink in a directory that is safe itself
+        fs::set_permissions(&parent, Permissions::from_mode(0o755)).unwrap();
+        fs::set_permissions(&target, Permissions::from_mode(0o777)).unwrap();
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        let err = check_ancestors_owned_by(&link, uid).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains(&format!("{:?} is writable", target)));
+    }
 }
@@ -0,0 +1,339 @@
+use crate::perm::MODE_MASK;
+use anyhow::Result;
+use std::ffi::CString;
+use std::fs;
+use std::fs::{File, Metadata, Permissions};
+use std::io;
+use std::io::{ErrorKind, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs as unix;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// some file systems (e.g. FAT) only store modification times with a granularity of two seconds
+const MTIME_TOLERANCE: Duration = Duration::from_secs(2);
+
+// security labels (e.g. SELinux) are preserved if possible, but the target file system may
+// assign its own labels
+const SECURITY_PREFIX: &str = "security.";
+
+// across file systems the tree is copied and verified before the source is removed
+pub fn move_item(source: &Path, target: &Path) -> Result<()> {
+    match fs::rename(source, target) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == ErrorKind::CrossesDevices => move_across(source, target),
+        Err(err) => Err(err)?,
+    }
+}
+
+fn move_across(source: &Path, target: &Path) -> Result<()> {
+    info!("Copying {:?} to {:?} across file systems", source, target);
+    if let Err(err) = copy_item(source, target).and_then(|()| verify_item(source, target)) {
+        warn!("Copy of {:?} failed, keeping the source", source);
+        // the copy may have failed before anything was created
+        if let Err(cleanup) = delete_item(target) {
+            let missing = cleanup
+                .downcast_ref::<io::Error>()
+                .is_some_and(|cleanup| cleanup.kind() == ErrorKind::NotFound);
+            if !missing {
+                warn!("Cannot remove the partial copy {:?}: {}", target, cleanup);
+            }
+        }
+        return Err(err);
+    }
+    delete_item(source)
+}
+
+// including directories without write access
+pub fn delete_item(path: &Path) -> Result<()> {
+    let meta = path.symlink_metadata()?;
+    if meta.is_dir() {
+        let mode = meta.permissions().mode();
+        if mode & 0o700 != 0o700 {
+            fs::set_permissions(path, Permissions::from_mode(mode | 0o700))?;
+        }
+        for entry in fs::read_dir(path)? {
+            delete_item(&entry?.path())?;
+        }
+        fs::remove_dir(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+fn copy_attributes(source: &Path, target: &Path) -> Result<()> {
+    for name in xattr::list(source)? {
+        if let Some(value) = xattr::get(source, &name)? {
+            if let Err(err) = xattr::set(target, &name, &value) {
+                if name.to_string_lossy().starts_with(SECURITY_PREFIX) {
+                    warn!("Cannot preserve {:?} of {:?}: {}", name, source, err);
+                } else {
+                    Err(anyhow!(
+                        "Cannot preserve {:?} of {:?}: {}",
+                        name,
+                        source,
+                        err
+                    ))?
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// sets the times by path, so that neither read nor write access to `target` is needed
+fn copy_times(meta: &Metadata, target: &Path) -> Result<()> {
+    let times = [
+        libc::timespec {
+            tv_sec: meta.atime(),
+            tv_nsec: meta.atime_nsec(),
+        },
+        libc::timespec {
+            tv_sec: meta.mtime(),
+            tv_nsec: meta.mtime_nsec(),
+        },
+    ];
+    let path = CString::new(target.as_os_str().as_bytes())?;
+    let flags = libc::AT_SYMLINK_NOFOLLOW;
+    if unsafe { libc::utimensat(libc::AT_FDCWD, path.as_ptr(), times.as_ptr(), flags) } != 0 {
+        Err(io::Error::last_os_error())?
+    }
+    Ok(())
+}
+
+// directory modes are applied last, so that a partial copy can still be removed
+pub fn copy_item(source: &Path, target: &Path) -> Result<()> {
+    let mut dirs = vec![];
+    copy_tree(source, target, &mut dirs)?;
+    // deepest first, adding children or changing their modes does not touch the parent's times
+    for (dir, meta) in dirs {
+        fs::set_permissions(&dir, meta.permissions())?;
+        copy_times(&meta, &dir)?;
+    }
+    Ok(())
+}
+
+fn copy_tree(source: &Path, target: &Path, dirs: &mut Vec<(PathBuf, Metadata)>) -> Result<()> {
+    let meta = source.symlink_metadata()?;
+    let file_type = meta.file_type();
+    if file_type.is_symlink() {
+        unix::symlink(source.read_link()?, target)?;
+    } else if file_type.is_file() {
+        fs::copy(source, target)?;
+        // the copy gets the mode of the source, which may not allow setting attributes
+        fs::set_permissions(target, Permissions::from_mode(0o600))?;
+        copy_attributes(source, target)?;
+        fs::set_permissions(target, meta.permissions())?;
+        copy_times(&meta, target)?;
+    } else if file_type.is_dir() {
+        fs::create_dir(target)?;
+        for entry in fs::read_dir(source)? {
+            let name = entry?.file_name();
+            copy_tree(&source.join(&name), &target.join(&name), dirs)?;
+        }
+        copy_attributes(source, target)?;
+        dirs.push((target.to_path_buf(), meta));
+    } else {
+        Err(anyhow!(
+            "Cannot copy {:?} because it is a special file",
+            source
+        ))?
+    }
+    Ok(())
+}
+
+fn same_contents(source: &Path, target: &Path) -> Result<bool> {
+    let mut source = File::open(source)?;
+    let mut target = File::open(target)?;
+    let mut source_buffer = [0; 8192];
+    let mut target_buffer = [0; 8192];
+    loop {
+        let read = source.read(&mut source_buffer)?;
+        if read == 0 {
+            return Ok(target.read(&mut target_buffer)? == 0);
+        }
+        target.read_exact(&mut target_buffer[..read])?;
+        if source_buffer[..read] != target_buffer[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+fn verify_attributes(source: &Path, target: &Path) -> Result<()> {
+    for name in xattr::list(source)? {
+        if name.to_string_lossy().starts_with(SECURITY_PREFIX) {
+            continue;
+        }
+        if xattr::get(source, &name)? != xattr::get(target, &name)? {
+            Err(anyhow!(
+                "Extended attribute {:?} of {:?} differs in copy",
+                name,
+                source
+            ))?
+        }
+    }
+    Ok(())
+}
+
+pub fn verify_item(source: &Path, target: &Path) -> Result<()> {
+    let source_meta = source.symlink_metadata()?;
+    let target_meta = target.symlink_metadata()?;
+    if source_meta.file_type() != target_meta.file_type() {
+        Err(anyhow!("Type of {:?} differs in copy", source))?
+    }
+    if source_meta.file_type().is_symlink() {
+        if source.read_link()? != target.read_link()? {
+            Err(anyhow!("Target of {:?} differs in copy", source))?
+        }
+        return Ok(());
+    }
+
+    let source_mode = source_meta.permissions().mode() & MODE_MASK;
+    let target_mode = target_meta.permissions().mode() & MODE_MASK;
+    if source_mode != target_mode {
+        Err(anyhow!(
+            "Mode of {:?} differs in copy: {:o} instead of {:o}",
+            source,
+            target_mode,
+            source_mode
+        ))?
+    }
+    let source_modified = source_meta.modified()?;
+    let target_modified = target_meta.modified()?;
+    let difference = source_modified
+        .duration_since(target_modified)
+        .or_else(|_| target_modified.duration_since(source_modified))?;
+    if difference > MTIME_TOLERANCE {
+        Err(anyhow!("Modification time of {:?} differs in copy", source))?
+    }
+    verify_attributes(source, target)?;
+
+    if source_meta.is_dir() {
+        let mut names = vec![];
+        for entry in fs::read_dir(source)? {
+            let name = entry?.file_name();
+            verify_item(&source.join(&name), &target.join(&name))?;
+            names.push(name);
+        }
+        if fs::read_dir(target)?.count() != names.len() {
+            Err(anyhow!("Copy of {:?} contains additional entries", source))?
+        }
+    } else if !same_contents(source, target)? {
+        Err(anyhow!("Contents of {:?} differ in copy", source))?
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transfer::*;
+    use std::fs::FileTimes;
+    use std::io::Write;
+    use std::time::SystemTime;
+    use tempfile::TempDir;
+
+    fn setup_tree(dir: &Path) {
+        let root = dir.join("source");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        File::create(root.join("file"))
+            .unwrap()
+            .write_all(b"contents")
+            .unwrap();
+        File::create(root.join("sub/script"))
+            .unwrap()
+            .write_all(b"#!/bin/sh")
+            .unwrap();
+        fs::set_permissions(root.join("sub/script"), Permissions::from_mode(0o750)).unwrap();
+        unix::symlink("../file", root.join("sub/link")).unwrap();
+        let past = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        File::open(root.join("file"))
+            .unwrap()
+            .set_times(FileTimes::new().set_modified(past))
+            .unwrap();
+        fs::set_permissions(root.join("sub"), Permissions::from_mode(0o700)).unwrap();
+    }
+
+    #[test]
+    fn test_copy_verify() {
+        let dir = TempDir::new().unwrap();
+        setup_tree(dir.path());
+        let source = dir.path().join("source");
+        let target = dir.path().join("target");
+        copy_item(&source, &target).unwrap();
+        verify_item(&source, &target).unwrap();
+        assert_eq!(
+            source.join("file").metadata().unwrap().modified().unwrap(),
+            target.join("file").metadata().unwrap().modified().unwrap()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "differ in copy")]
+    fn test_verify_contents() {
+        let dir = TempDir::new().unwrap();
+        setup_tree(dir.path());
+        let source = dir.path().join("source");
+        let target = dir.path().join("target");
+        copy_item(&source, &target).unwrap();
+        let meta = target.join("file").metadata().unwrap();
+        File::create(target.join("file"))
+            .unwrap()
+            .write_all(b"changed!")
+            .unwrap();
+        copy_times(&meta, &target.join("file")).unwrap();
+        verify_item(&source, &target).unwrap();
+    }
+
+    #[test]
+    fn test_move() {
+        let dir = TempDir::new().unwrap();
+        setup_tree(dir.path());
+        let source = dir.path().join("source");
+        let target = dir.path().join("target");
+        move_item(&source, &target).unwrap();
+        assert!(source.symlink_metadata().is_err());
+        assert_eq!(
+            0o750,
+            MODE_MASK
+                & target
+                    .join("sub/script")
+                    .metadata()
+                    .unwrap()
+                    .permissions()
+                    .mode()
+        );
+    }
+
+    #[test]
+    fn test_copy_restricted() {
+        let dir = TempDir::new().unwrap();
+        setup_tree(dir.path());
+        let source = dir.path().join("source");
+        let target = dir.path().join("target");
+        xattr::set(source.join("file"), "user.test", b"value").unwrap();
+        for (path, mode) in [("file", 0o400), ("sub/script", 0o500), ("sub", 0o500)] {
+            fs::set_permissions(source.join(path), Permissions::from_mode(mode)).unwrap();
+        }
+        copy_item(&source, &target).unwrap();
+        verify_item(&source, &target).unwrap();
+        delete_item(&target).unwrap();
+        assert!(target.symlink_metadata().is_err());
+    }
+
+    #[test]
+    fn test_move_across_failure() {
+        let dir = TempDir::new().unwrap();
+        setup_tree(dir.path());
+        let source = dir.path().join("source");
+        let target = dir.path().join("target");
+        let fifo = CString::new(source.join("sub/fifo").as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+
+        let err = move_across(&source, &target).unwrap_err();
+        assert!(err.to_string().contains("special file"));
+        assert!(target.symlink_metadata().is_err());
+        assert!(source.join("sub/script").is_file());
+    }
+}
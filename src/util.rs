@@ -1,6 +1,10 @@
 use crate::perm::{Perm, Perms};
 use anyhow::{Error, Result};
+use std::ffi::OsStr;
 use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
 
 pub static APP_VERSION: &str = crate_version!();
@@ -16,13 +20,36 @@ pub fn result_from_option<T>(opt: Option<T>, msg: String) -> Result<T> {
     opt.ok_or_else(|| Error::msg(msg))
 }
 
-pub fn is_unique<T: Ord + Clone>(vec: &Vec<T>) -> bool {
-    let mut vec2 = vec.clone();
+pub fn write_atomically(path: &Path, contents: &str) -> Result<()> {
+    let mut temporary = path.as_os_str().to_os_string();
+    temporary.push(".tmp");
+    let mut file = OpenOptions::new()
+        .truncate(true)
+        .write(true)
+        .create(true)
+        .open(&temporary)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(temporary, path)?;
+    Ok(())
+}
+
+pub fn is_unique<T: Ord + Clone>(vec: &[T]) -> bool {
+    let mut vec2 = vec.to_vec();
     vec2.sort();
     vec2.dedup();
     vec2.len() == vec.len()
 }
 
+// empty paths are skipped
+pub fn split_paths(input: &[u8], separator: u8) -> Vec<PathBuf> {
+    input
+        .split(|byte| *byte == separator)
+        .filter(|path| !path.is_empty())
+        .map(|path| PathBuf::from(OsStr::from_bytes(path)))
+        .collect()
+}
+
 pub fn unexpected_files(dir: &Path, files: &[PathBuf], expect_exists: bool) -> Vec<PathBuf> {
     files
         .iter()
@@ -37,6 +64,28 @@ pub fn unexpected_files(dir: &Path, files: &[PathBuf], expect_exists: bool) -> V
         .collect()
 }
 
+// relative to `root` without following symlinks, directories precede their children
+pub fn walk(root: &Path) -> Result<Vec<PathBuf>> {
+    fn go(root: &Path, dir: &Path, entries: &mut Vec<PathBuf>) -> Result<()> {
+        let mut children = fs::read_dir(root.join(dir))?
+            .map(|entry| Ok(dir.join(entry?.file_name())))
+            .collect::<Result<Vec<PathBuf>>>()?;
+        children.sort();
+        for child in children {
+            let is_dir = root.join(&child).symlink_metadata()?.is_dir();
+            entries.push(child.clone());
+            if is_dir {
+                go(root, &child, entries)?;
+            }
+        }
+        Ok(())
+    }
+
+    let mut entries = vec![];
+    go(root, Path::new(""), &mut entries)?;
+    Ok(entries)
+}
+
 fn file_meta(buf: &PathBuf) -> Result<fs::Metadata> {
     let meta = fs::metadata(buf.clone())?;
     if !meta.is_file() {
@@ -95,17 +144,31 @@ impl Executable {
 mod test {
 
     use crate::perm::{Perms, MODE_MASK};
-    use crate::util::{is_unique, Executable};
+    use crate::util::{is_unique, split_paths, Executable};
     use std::fs;
     use std::fs::Permissions;
     use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
     use tempfile::NamedTempFile;
 
     #[test]
     fn test_unique() {
-        assert!(is_unique(&vec![1, 2, 3]));
-        assert!(!is_unique(&vec![1, 2, 2]));
-        assert!(!is_unique(&vec![2, 1, 2]));
+        assert!(is_unique(&[1, 2, 3]));
+        assert!(!is_unique(&[1, 2, 2]));
+        assert!(!is_unique(&[2, 1, 2]));
+    }
+
+    #[test]
+    fn test_split_paths() {
+        assert_eq!(
+            split_paths(b".vimrc\n.config/app\n\n", b'\n'),
+            vec![PathBuf::from(".vimrc"), PathBuf::from(".config/app")]
+        );
+        assert_eq!(
+            split_paths(b"with\nnewline\0.vimrc\0", b'\0'),
+            vec![PathBuf::from("with\nnewline"), PathBuf::from(".vimrc")]
+        );
+        assert!(split_paths(b"", b'\n').is_empty());
     }
 
     #[test]